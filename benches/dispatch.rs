@@ -0,0 +1,51 @@
+/**
+* dispatch benchmark
+* feeds the shell a script that runs the same command many times
+* and reports the time per command for a builtin and an external command.
+*
+* run with `cargo bench --bench dispatch`
+*/
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 500;
+const SAMPLES: usize = 5;
+
+fn run_script(line: &str) -> Duration {
+    let mut script = String::new();
+    for _ in 0..ITERATIONS {
+        script.push_str(line);
+        script.push('\n');
+    }
+    script.push_str("exit 0\n");
+
+    let started = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("should spawn the shell");
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success());
+    started.elapsed()
+}
+
+fn bench(name: &str, line: &str) {
+    let mut samples: Vec<Duration> = (0..SAMPLES).map(|_| run_script(line)).collect();
+    samples.sort();
+    let median = samples[SAMPLES / 2];
+    println!(
+        "{:<10} {:>10.1?}/iter (median of {} runs x {} commands)",
+        name,
+        median / ITERATIONS as u32,
+        SAMPLES,
+        ITERATIONS
+    );
+}
+
+fn main() {
+    bench("builtin", "echo hi");
+    bench("external", "true");
+}
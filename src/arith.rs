@@ -0,0 +1,367 @@
+use std::fmt;
+
+#[derive(PartialEq, Debug)]
+pub enum ArithError {
+    // the rest of the expression from the token that could not be read
+    Syntax(String),
+    InvalidNumber(String),
+    DivisionByZero,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithError::Syntax(token) if token.is_empty() => write!(f, "syntax error: operand expected"),
+            ArithError::Syntax(token) => write!(f, "syntax error: invalid arithmetic operator (error token is \"{}\")", token),
+            ArithError::InvalidNumber(number) => write!(f, "{}: invalid number", number),
+            ArithError::DivisionByZero => write!(f, "division by 0"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Token<'a> {
+    Number(i64),
+    Name(&'a str),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 22] = [
+    "+", "-", "*", "/", "%",
+    "<<", ">>", "<=", ">=", "==", "!=", "<", ">",
+    "&&", "||", "&", "|", "^", "~", "!", "?", ":",
+];
+
+/**
+* read an integer literal: `0x` for hex, a leading `0` for octal
+*/
+fn number(word: &str) -> Result<i64, ArithError> {
+    let invalid = || ArithError::InvalidNumber(word.to_string());
+    let (digits, radix) = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        (hex, 16)
+    } else if word.len() > 1 && word.starts_with('0') {
+        (&word[1..], 8)
+    } else {
+        (word, 10)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    u64::from_str_radix(digits, radix).map(|n| n as i64).map_err(|_| invalid())
+}
+
+/**
+* split the expression into tokens with the offset each one starts at
+*/
+fn tokens(src: &str) -> Result<Vec<(Token<'_>, usize)>, ArithError> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    while offset < src.len() {
+        let rest = &src[offset..];
+        let trimmed = rest.trim_start();
+        if trimmed.is_empty() {
+            break;
+        }
+        offset += rest.len() - trimmed.len();
+        let rest = trimmed;
+
+        if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let token = if word.starts_with(|c: char| c.is_ascii_digit()) {
+                Token::Number(number(word)?)
+            } else {
+                Token::Name(word)
+            };
+            result.push((token, offset));
+            offset += end;
+            continue;
+        }
+        let token = if rest.starts_with('(') {
+            (Token::Open, 1)
+        } else if rest.starts_with(')') {
+            (Token::Close, 1)
+        } else if let Some(op) = OPERATORS.iter().filter(|op| rest.starts_with(**op)).max_by_key(|op| op.len()) {
+            (Token::Operator(op), op.len())
+        } else {
+            return Err(ArithError::Syntax(rest.trim_end().to_string()));
+        };
+        result.push((token.0, offset));
+        offset += token.1;
+    }
+    Ok(result)
+}
+
+/**
+* binding power of the binary operators, as in C
+*/
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "|" => Some(3),
+        "^" => Some(4),
+        "&" => Some(5),
+        "==" | "!=" => Some(6),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "<<" | ">>" => Some(8),
+        "+" | "-" => Some(9),
+        "*" | "/" | "%" => Some(10),
+        _ => None,
+    }
+}
+
+struct Parser<'a, 'b> {
+    src: &'a str,
+    tokens: Vec<(Token<'a>, usize)>,
+    pos: usize,
+    lookup: &'b dyn Fn(&str) -> Option<String>,
+    // inside a branch that is not taken, where errors of the value do not count
+    noeval: usize,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).map(|(token, _)| *token)
+    }
+
+    fn error(&self) -> ArithError {
+        let offset = self.tokens.get(self.pos).map_or(self.src.len(), |(_, offset)| *offset);
+        ArithError::Syntax(self.src[offset..].trim().to_string())
+    }
+
+    fn expect(&mut self, token: Token<'a>) -> Result<(), ArithError> {
+        if self.peek() != Some(token) {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, ArithError> {
+        let value = (self.lookup)(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        match value.strip_prefix('-') {
+            Some(value) => number(value).map(i64::wrapping_neg),
+            None => number(value),
+        }
+    }
+
+    fn apply(&self, op: &str, lhs: i64, rhs: i64) -> Result<i64, ArithError> {
+        let value = match op {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 && self.noeval > 0 => 0,
+            "/" | "%" if rhs == 0 => return Err(ArithError::DivisionByZero),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "<" => (lhs < rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "&" => lhs & rhs,
+            "^" => lhs ^ rhs,
+            "|" => lhs | rhs,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            "||" => (lhs != 0 || rhs != 0) as i64,
+            _ => unreachable!("unknown operator {}", op),
+        };
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<i64, ArithError> {
+        let token = self.peek().ok_or_else(|| self.error())?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(n),
+            Token::Name(name) => self.variable(name),
+            Token::Operator("-") => Ok(self.primary()?.wrapping_neg()),
+            Token::Operator("+") => self.primary(),
+            Token::Operator("!") => Ok((self.primary()? == 0) as i64),
+            Token::Operator("~") => Ok(!self.primary()?),
+            Token::Open => {
+                let value = self.ternary()?;
+                self.expect(Token::Close)?;
+                Ok(value)
+            },
+            _ => {
+                self.pos -= 1;
+                Err(self.error())
+            },
+        }
+    }
+
+    /**
+    * evaluate with `noeval` raised when `skip` is set
+    */
+    fn skipping<T>(&mut self, skip: bool, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.noeval += skip as usize;
+        let value = f(self);
+        self.noeval -= skip as usize;
+        value
+    }
+
+    /**
+    * precedence climbing: fold operators binding tighter than `min`
+    */
+    fn expression(&mut self, min: u8) -> Result<i64, ArithError> {
+        let mut lhs = self.primary()?;
+        while let Some(Token::Operator(op)) = self.peek() {
+            let Some(prec) = precedence(op).filter(|prec| *prec > min) else {
+                break;
+            };
+            self.pos += 1;
+            let skip = (op == "&&" && lhs == 0) || (op == "||" && lhs != 0);
+            let rhs = self.skipping(skip, |parser| parser.expression(prec))?;
+            lhs = self.apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    /**
+    * `cond ? a : b`, the loosest operator, grouping to the right
+    */
+    fn ternary(&mut self) -> Result<i64, ArithError> {
+        let condition = self.expression(0)?;
+        if self.peek() != Some(Token::Operator("?")) {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.skipping(condition == 0, |parser| parser.ternary())?;
+        self.expect(Token::Operator(":"))?;
+        let otherwise = self.skipping(condition != 0, |parser| parser.ternary())?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+}
+
+/**
+* evaluate an integer expression as `$((...))` does, reading names with `lookup`
+*/
+pub fn evaluate(src: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<i64, ArithError> {
+    let tokens = tokens(src)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { src, tokens, pos: 0, lookup, noeval: 0 };
+    let value = parser.ternary()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn none(_: &str) -> Option<String> {
+        None
+    }
+
+    fn numbers(name: &str) -> Option<String> {
+        match name {
+            "a" => Some("3".to_string()),
+            "b" => Some("7".to_string()),
+            "neg" => Some("-2".to_string()),
+            "word" => Some("abc".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_basic() {
+        assert_eq!(evaluate("1 + 2 * 3", &none), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3", &none), Ok(9));
+        assert_eq!(evaluate("10 - 4 - 3", &none), Ok(3));
+        assert_eq!(evaluate("7 / 2 % 3", &none), Ok(0));
+        assert_eq!(evaluate("", &none), Ok(0));
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(evaluate(" -5 + 3 ", &none), Ok(-2));
+        assert_eq!(evaluate("2 - -3", &none), Ok(5));
+        assert_eq!(evaluate("-(2 + 3) * +2", &none), Ok(-10));
+        assert_eq!(evaluate("-7 / 2", &none), Ok(-3));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(evaluate("0xff", &none), Ok(255));
+        assert_eq!(evaluate("0X1f", &none), Ok(31));
+        assert_eq!(evaluate("010", &none), Ok(8));
+        assert_eq!(evaluate("0", &none), Ok(0));
+        assert_eq!(evaluate("09", &none), Err(ArithError::InvalidNumber("09".to_string())));
+        assert_eq!(evaluate("0x", &none), Err(ArithError::InvalidNumber("0x".to_string())));
+    }
+
+    #[test]
+    fn test_bitwise_and_shift() {
+        assert_eq!(evaluate("1 << 3 | 1", &none), Ok(9));
+        assert_eq!(evaluate("6 & 3", &none), Ok(2));
+        assert_eq!(evaluate("6 ^ 3", &none), Ok(5));
+        assert_eq!(evaluate("~0", &none), Ok(-1));
+        assert_eq!(evaluate("256 >> 2 + 2", &none), Ok(16));
+        assert_eq!(evaluate("1 | 2 ^ 3 & 4", &none), Ok(3));
+    }
+
+    #[test]
+    fn test_comparison_and_logic() {
+        assert_eq!(evaluate("1 < 2 == 1", &none), Ok(1));
+        assert_eq!(evaluate("2 <= 1", &none), Ok(0));
+        assert_eq!(evaluate("3 >= 3 && 4 > 5", &none), Ok(0));
+        assert_eq!(evaluate("0 || 2 != 2 || 1", &none), Ok(1));
+        assert_eq!(evaluate("!0 + !5", &none), Ok(1));
+        assert_eq!(evaluate("1 + 1 == 2 & 1", &none), Ok(1));
+        assert_eq!(evaluate("1 || 0 && 0", &none), Ok(1));
+    }
+
+    #[test]
+    fn test_ternary() {
+        assert_eq!(evaluate("a > b ? a : b", &numbers), Ok(7));
+        assert_eq!(evaluate("a < b ? a : b", &numbers), Ok(3));
+        assert_eq!(evaluate("0 ? 1 : 0 ? 2 : 3", &none), Ok(3));
+        assert_eq!(evaluate("1 ? 0 ? 4 : 5 : 6", &none), Ok(5));
+        assert_eq!(evaluate("(1 ? 2 : 3) * 2", &none), Ok(4));
+        assert_eq!(evaluate("1 ? 2", &none), Err(ArithError::Syntax("".to_string())));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        assert_eq!(evaluate("0 && 1 / 0", &none), Ok(0));
+        assert_eq!(evaluate("1 || 1 % 0", &none), Ok(1));
+        assert_eq!(evaluate("1 ? 2 : 1 / 0", &none), Ok(2));
+        assert_eq!(evaluate("1 && 1 / 0", &none), Err(ArithError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(evaluate("a * b", &numbers), Ok(21));
+        assert_eq!(evaluate("neg - unset", &numbers), Ok(-2));
+        assert_eq!(evaluate("word", &numbers), Err(ArithError::InvalidNumber("abc".to_string())));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("1.5 + 1", &none), Err(ArithError::Syntax(".5 + 1".to_string())));
+        assert_eq!(evaluate("1 +", &none), Err(ArithError::Syntax("".to_string())));
+        assert_eq!(evaluate("(1 + 2", &none), Err(ArithError::Syntax("".to_string())));
+        assert_eq!(evaluate("1 2", &none), Err(ArithError::Syntax("2".to_string())));
+        assert_eq!(evaluate("1 / 0", &none), Err(ArithError::DivisionByZero));
+        assert_eq!(evaluate("1 % (2 - 2)", &none), Err(ArithError::DivisionByZero));
+        assert_eq!(
+            ArithError::Syntax(".5".to_string()).to_string(),
+            "syntax error: invalid arithmetic operator (error token is \".5\")"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::sys::RawMode;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/**
+* what pressing tab does to the word being typed
+*/
+#[derive(PartialEq, Debug)]
+pub enum Completion {
+    // nothing to add, ring the bell
+    None,
+    Insert(String),
+    // the candidates to show, on a second tab
+    List(Vec<String>),
+}
+
+/**
+* the longest prefix all of `words` share
+*/
+pub fn common_prefix(words: &[String]) -> &str {
+    let Some(first) = words.first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(word.len()), |((i, _), _)| i.min(len));
+    }
+    &first[..len]
+}
+
+/**
+* the files and directories `partial` can be completed to, relative to `pwd` unless absolute.
+* directories end with `/`, and hidden entries need `partial` to name them with a leading `.`
+*/
+pub fn path_candidates(partial: &str, pwd: &Path) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(pwd.join(dir)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/**
+* complete `word` against `candidates`, which all start with it.
+* a single match is finished with a space unless it is a directory,
+* several are extended to their common prefix and listed when tab is `repeated`
+*/
+pub fn complete(word: &str, candidates: &[String], repeated: bool) -> Completion {
+    match candidates {
+        [] => Completion::None,
+        [only] if only.ends_with('/') => Completion::Insert(only[word.len()..].to_string()),
+        [only] => Completion::Insert(format!("{} ", &only[word.len()..])),
+        _ => {
+            let prefix = common_prefix(candidates);
+            if prefix.len() > word.len() {
+                Completion::Insert(prefix[word.len()..].to_string())
+            } else if repeated {
+                Completion::List(candidates.to_vec())
+            } else {
+                Completion::None
+            }
+        },
+    }
+}
+
+/**
+* read a line from the terminal, completing the last word on tab.
+* `candidates` is told whether that word is the first one, the command name.
+* the line ends with its newline like `read_line`, and `None` is the end of input.
+* when standard input is no terminal to take over this fails as `Unsupported`
+*/
+pub fn read_line(prompt: &str, candidates: &dyn Fn(&str, bool) -> Vec<String>) -> io::Result<Option<String>> {
+    let _raw = RawMode::enable().map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))?;
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    let mut pending = Vec::new();
+    let mut last_was_tab = false;
+    write!(stdout, "{}", prompt)?;
+    stdout.flush()?;
+
+    loop {
+        let mut byte = [0u8];
+        if stdin.read(&mut byte)? == 0 {
+            return Ok(if line.is_empty() { None } else { Some(line) });
+        }
+        let tab = byte[0] == b'\t';
+        match byte[0] {
+            b'\n' | b'\r' => {
+                writeln!(stdout)?;
+                line.push('\n');
+                return Ok(Some(line));
+            },
+            // ctrl-c gives up the line for a fresh prompt
+            3 => {
+                write!(stdout, "^C\n{}", prompt)?;
+                line.clear();
+                pending.clear();
+            },
+            // ctrl-d
+            4 if line.is_empty() => return Ok(None),
+            // backspace
+            8 | 127 => {
+                if line.pop().is_some() {
+                    write!(stdout, "\x08 \x08")?;
+                }
+            },
+            // escape sequences such as the arrow keys are not supported
+            0x1b => {
+                let mut sequence = [0u8; 2];
+                stdin.read_exact(&mut sequence)?;
+            },
+            b'\t' => {
+                let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                let word = &line[start..];
+                match complete(word, &candidates(word, start == 0), last_was_tab) {
+                    Completion::None => write!(stdout, "\x07")?,
+                    Completion::Insert(text) => {
+                        write!(stdout, "{}", text)?;
+                        line.push_str(&text);
+                    },
+                    Completion::List(words) => write!(stdout, "\n{}\n{}{}", words.join("  "), prompt, line)?,
+                }
+            },
+            byte if byte < 0x20 => {},
+            byte => {
+                pending.push(byte);
+                if let Ok(text) = std::str::from_utf8(&pending) {
+                    write!(stdout, "{}", text)?;
+                    line.push_str(text);
+                    pending.clear();
+                } else if pending.len() >= 4 {
+                    pending.clear();
+                }
+            },
+        }
+        last_was_tab = tab;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(a: &[&str]) -> Vec<String> {
+        a.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(common_prefix(&words(&["export", "exit", "exec"])), "ex");
+        assert_eq!(common_prefix(&words(&["echo", "echo"])), "echo");
+        assert_eq!(common_prefix(&words(&["ab", "abc"])), "ab");
+        assert_eq!(common_prefix(&words(&["a", "b"])), "");
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("ech", &words(&["echo"]), false), Completion::Insert("o ".to_string()));
+        assert_eq!(complete("e", &words(&["exit", "export"]), false), Completion::Insert("x".to_string()));
+        assert_eq!(complete("ex", &words(&["exit", "export"]), false), Completion::None);
+        assert_eq!(complete("ex", &words(&["exit", "export"]), true), Completion::List(words(&["exit", "export"])));
+        assert_eq!(complete("zz", &[], true), Completion::None);
+        assert_eq!(complete("sr", &words(&["src/"]), false), Completion::Insert("c/".to_string()));
+    }
+
+    #[test]
+    fn test_path_candidates() {
+        let dir = std::env::temp_dir().join(format!("editor_path_candidates_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        for file in ["src/main.rs", "src/more.rs", "src/.hidden", "readme"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        assert_eq!(path_candidates("", &dir), ["readme", "src/"]);
+        assert_eq!(path_candidates("s", &dir), ["src/"]);
+        assert_eq!(path_candidates("src/m", &dir), ["src/main.rs", "src/more.rs"]);
+        assert_eq!(path_candidates("src/", &dir), ["src/main.rs", "src/more.rs", "src/nested/"]);
+        assert_eq!(path_candidates("src/.", &dir), ["src/.hidden"]);
+        assert!(path_candidates("missing/", &dir).is_empty());
+
+        let absolute = format!("{}/src/ma", dir.display());
+        assert_eq!(path_candidates(&absolute, Path::new("/")), [format!("{}/src/main.rs", dir.display())]);
+    }
+}
@@ -0,0 +1,93 @@
+/**
+* decode backslash escapes as `echo -e` does.
+* unknown escapes are kept as they are.
+* the flag tells whether a `\c` stopped the output early
+*/
+pub fn decode(src: &str) -> (String, bool) {
+    let mut result = String::new();
+    let mut chars = src.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        let Some(next) = chars.next() else {
+            result.push('\\');
+            break;
+        };
+        match next {
+            '\\' => result.push('\\'),
+            'c' => return (result, true),
+            'a' => result.push('\x07'),
+            'b' => result.push('\x08'),
+            'e' | 'E' => result.push('\x1b'),
+            'f' => result.push('\x0c'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            'v' => result.push('\x0b'),
+            '0' => {
+                let code = take_digits(&mut chars, 8, 3);
+                result.push(char::from_u32(code.unwrap_or(0)).unwrap_or('\0'));
+            },
+            'x' => match take_digits(&mut chars, 16, 2) {
+                Some(code) => result.push(char::from_u32(code).unwrap_or('\0')),
+                None => result.push_str("\\x"),
+            },
+            other => {
+                result.push('\\');
+                result.push(other);
+            },
+        }
+    }
+    (result, false)
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut code = None;
+    for _ in 0..max {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        chars.next();
+        code = Some(code.unwrap_or(0) * radix + digit);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(decode("abc").0, "abc");
+    }
+
+    #[test]
+    fn test_simple_escapes() {
+        assert_eq!(decode(r"a\tb\nc").0, "a\tb\nc");
+        assert_eq!(decode(r"a\\b").0, "a\\b");
+        assert_eq!(decode(r"\a\b\e\f\r\v").0, "\x07\x08\x1b\x0c\r\x0b");
+    }
+
+    #[test]
+    fn test_numeric_escapes() {
+        assert_eq!(decode(r"\0101\x41").0, "AA");
+        assert_eq!(decode(r"\0").0, "\0");
+        assert_eq!(decode(r"\x4").0, "\x04");
+        assert_eq!(decode(r"\xg").0, r"\xg");
+    }
+
+    #[test]
+    fn test_stop_escape() {
+        assert_eq!(decode(r"hello\cworld"), ("hello".to_string(), true));
+        assert_eq!(decode(r"hello"), ("hello".to_string(), false));
+    }
+
+    #[test]
+    fn test_unknown_escape() {
+        assert_eq!(decode(r"\q").0, r"\q");
+        assert_eq!(decode("end\\").0, "end\\");
+    }
+}
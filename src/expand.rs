@@ -0,0 +1,379 @@
+use crate::arith;
+use crate::tokenize::substitution;
+use crate::unescape::unescape;
+
+/**
+* what expansion needs from the shell
+*/
+pub trait Context {
+    fn variable(&self, name: &str) -> Option<String>;
+    // run `command` and give its output with the trailing newlines removed
+    fn substitute(&self, command: &str) -> String;
+    // the home directory of `user`, or of the current user when it is empty
+    fn home_dir(&self, user: &str) -> Option<String>;
+}
+
+/**
+* escape `value` so that unescaping gives it back as it is,
+* inside double quotes when `in_double` is set.
+* outside them operators and `=` are escaped too, so expanded text is never taken for syntax
+*/
+pub fn literal(value: &str, in_double: bool) -> String {
+    let mut result = String::new();
+    for ch in value.chars() {
+        let special = if in_double {
+            matches!(ch, '"' | '\\')
+        } else {
+            matches!(ch, '"' | '\'' | '\\' | '|' | '&' | ';' | '<' | '>' | '=')
+        };
+        if special {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+fn is_name_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/**
+* whether `name` can be the name of a variable
+*/
+pub fn is_name(name: &str) -> bool {
+    name.starts_with(is_name_start) && name.chars().all(is_name_char)
+}
+
+/**
+* take the name of the parameter after a `$`, either bare or in braces.
+* nothing is consumed when it is not a parameter
+*/
+fn parameter(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut lookahead = chars.clone();
+    let name = match lookahead.next()? {
+        '?' => "?".to_string(),
+        // only a single digit is positional without braces, `$10` is `${1}0`
+        ch if ch.is_ascii_digit() => ch.to_string(),
+        '{' => {
+            let mut name = String::new();
+            loop {
+                match lookahead.next()? {
+                    '}' => break,
+                    ch => name.push(ch),
+                }
+            }
+            let positional = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit());
+            let valid = name == "?" || positional || is_name(&name);
+            if !valid {
+                return None;
+            }
+            name
+        },
+        ch if is_name_start(ch) => {
+            let mut name = ch.to_string();
+            while let Some(ch) = lookahead.next_if(|c| is_name_char(*c)) {
+                name.push(ch);
+            }
+            name
+        },
+        _ => return None,
+    };
+    *chars = lookahead;
+    Some(name)
+}
+
+/**
+* take the expression of a `$((...))` after its `$`, up to the balanced `))`
+*/
+fn arithmetic(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut lookahead = chars.clone();
+    if lookahead.next() != Some('(') || lookahead.next() != Some('(') {
+        return None;
+    }
+    let mut expression = String::new();
+    let mut depth = 0;
+    loop {
+        let ch = lookahead.next()?;
+        match ch {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                if lookahead.next() != Some(')') {
+                    return None;
+                }
+                break;
+            },
+            ')' => depth -= 1,
+            _ => {},
+        }
+        expression.push(ch);
+    }
+    *chars = lookahead;
+    Some(expression)
+}
+
+/**
+* take the command of a `$(...)` after its `$`
+*/
+fn command(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let rest: String = std::iter::once('$').chain(chars.clone()).collect();
+    let (found, _) = substitution(&rest)?;
+    let command = found[2..found.len() - 1].to_string();
+    for _ in found.chars().skip(1) {
+        chars.next();
+    }
+    Some(command)
+}
+
+/**
+* take the command of a backquoted substitution after its opening backquote.
+* a backslash only escapes `$`, `` ` `` and itself there
+*/
+fn backquoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut lookahead = chars.clone();
+    let mut command = String::new();
+    loop {
+        match lookahead.next()? {
+            '`' => break,
+            '\\' if lookahead.peek().is_some_and(|c| matches!(c, '$' | '`' | '\\')) => command.push(lookahead.next()?),
+            ch => command.push(ch),
+        }
+    }
+    *chars = lookahead;
+    Some(command)
+}
+
+/**
+* expand a `~` or `~user` prefix ending at the first `/`.
+* a quoted or escaped prefix is not a user name, so it stays.
+* the directory comes back quoted, as it is never globbed
+*/
+fn tilde<'a>(src: &'a str, context: &dyn Context) -> Option<(String, &'a str)> {
+    let rest = src.strip_prefix('~')?;
+    let end = rest.find('/').unwrap_or(rest.len());
+    let user = &rest[..end];
+    if !user.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return None;
+    }
+    Some((format!("\"{}\"", literal(&context.home_dir(user)?, true)), &rest[end..]))
+}
+
+/**
+* expand the tilde, parameters, arithmetic and command substitutions of a raw token,
+* leaving single-quoted text alone.
+* the result is still a raw token: values are escaped to stay literal
+*/
+pub fn expand(src: &str, context: &dyn Context) -> Result<String, String> {
+    let mut result = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let src = match tilde(src, context) {
+        Some((home, rest)) => {
+            result.push_str(&home);
+            rest
+        },
+        None => src,
+    };
+    let mut chars = src.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if !in_single => {
+                result.push(ch);
+                if let Some(next) = chars.next() {
+                    result.push(next);
+                }
+            },
+            '\'' if !in_double => {
+                in_single = !in_single;
+                result.push(ch);
+            },
+            '"' if !in_single => {
+                in_double = !in_double;
+                result.push(ch);
+            },
+            '`' if !in_single => match backquoted(&mut chars) {
+                Some(command) => result.push_str(&literal(&context.substitute(&command), in_double)),
+                None => result.push(ch),
+            },
+            '$' if !in_single => {
+                if let Some(expression) = arithmetic(&mut chars) {
+                    let lookup = |name: &str| context.variable(name);
+                    let value = arith::evaluate(&unescape(&expand(&expression, context)?), &lookup)
+                        .map_err(|e| format!("{}: {}", expression.trim(), e))?;
+                    result.push_str(&value.to_string());
+                    continue;
+                }
+                if let Some(command) = command(&mut chars) {
+                    result.push_str(&literal(&context.substitute(&command), in_double));
+                    continue;
+                }
+                match parameter(&mut chars) {
+                    Some(name) => result.push_str(&literal(&context.variable(&name).unwrap_or_default(), in_double)),
+                    None => result.push(ch),
+                }
+            },
+            _ => result.push(ch),
+        }
+    }
+    Ok(result)
+}
+
+/**
+* split an expanded raw token into fields at whitespace outside quotes and escapes.
+* the tokenizer already split at such whitespace, so any left came from an unquoted expansion
+*/
+pub fn split_fields(raw: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            ' ' | '\t' | '\n' if !in_single && !in_double => {
+                if !field.is_empty() {
+                    fields.push(std::mem::take(&mut field));
+                }
+                continue;
+            },
+            '\\' if !in_single => {
+                field.push(ch);
+                if let Some(next) = chars.next() {
+                    field.push(next);
+                }
+                continue;
+            },
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            _ => {},
+        }
+        field.push(ch);
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Vars<F: Fn(&str) -> Option<String>>(F);
+    impl<F: Fn(&str) -> Option<String>> Context for Vars<F> {
+        fn variable(&self, name: &str) -> Option<String> {
+            (self.0)(name)
+        }
+        fn substitute(&self, command: &str) -> String {
+            format!("[{}]", command)
+        }
+        fn home_dir(&self, user: &str) -> Option<String> {
+            match user {
+                "" => (self.0)("HOME"),
+                "other" => Some("/home/o'ther".to_string()),
+                _ => None,
+            }
+        }
+    }
+
+    fn status(name: &str) -> Option<String> {
+        (name == "?").then(|| "1".to_string())
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(expand("$?", &Vars(status)).unwrap(), "1");
+        assert_eq!(expand("a$?b", &Vars(status)).unwrap(), "a1b");
+        assert_eq!(expand("\"$?\"", &Vars(status)).unwrap(), "\"1\"");
+    }
+
+    #[test]
+    fn test_quoted_dollar() {
+        assert_eq!(expand("'$?'", &Vars(status)).unwrap(), "'$?'");
+        assert_eq!(expand("\\$?", &Vars(status)).unwrap(), "\\$?");
+        assert_eq!(expand("$", &Vars(status)).unwrap(), "$");
+        assert_eq!(expand("$@", &Vars(status)).unwrap(), "$@");
+        assert_eq!(expand("${", &Vars(status)).unwrap(), "${");
+        assert_eq!(expand("${a-b}", &Vars(status)).unwrap(), "${a-b}");
+    }
+
+    fn home(name: &str) -> Option<String> {
+        (name == "HOME").then(|| "/home/user".to_string())
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(expand("$HOME", &Vars(home)).unwrap(), "/home/user");
+        assert_eq!(expand("$HOME/bin", &Vars(home)).unwrap(), "/home/user/bin");
+        assert_eq!(expand("${HOME}s", &Vars(home)).unwrap(), "/home/users");
+        assert_eq!(expand("$HOMEs", &Vars(home)).unwrap(), "");
+        assert_eq!(expand("'$HOME'", &Vars(home)).unwrap(), "'$HOME'");
+        assert_eq!(expand("\"'$HOME'\"", &Vars(home)).unwrap(), "\"'/home/user'\"");
+        assert_eq!(expand("${UNDEFINED}x", &Vars(home)).unwrap(), "x");
+        assert_eq!(expand("$1${10}$10", &Vars(|name| Some(format!("[{}]", name)))).unwrap(), "[1][10][1]0");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(expand("$(( -5 + 3 ))", &Vars(status)).unwrap(), "-2");
+        assert_eq!(expand("x$((0xff))y", &Vars(status)).unwrap(), "x255y");
+        assert_eq!(expand("\"$(( ($? + 1) * 2 ))\"", &Vars(status)).unwrap(), "\"4\"");
+        assert_eq!(expand("'$((1))'", &Vars(status)).unwrap(), "'$((1))'");
+        assert_eq!(expand("$((1 + 2)", &Vars(status)).unwrap(), "$((1 + 2)");
+        assert_eq!(
+            expand("$(( 1.5 ))", &Vars(status)),
+            Err("1.5: syntax error: invalid arithmetic operator (error token is \".5\")".to_string())
+        );
+        assert_eq!(expand("$((1/0))", &Vars(status)), Err("1/0: division by 0".to_string()));
+        assert_eq!(expand("$(( HOME == 0 ))", &Vars(status)).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_tilde() {
+        assert_eq!(expand("~", &Vars(home)).unwrap(), "\"/home/user\"");
+        assert_eq!(expand("~/foo", &Vars(home)).unwrap(), "\"/home/user\"/foo");
+        assert_eq!(unescape(&expand("~other/x", &Vars(home)).unwrap()), "/home/o'ther/x");
+        assert_eq!(expand("~nobody_here", &Vars(home)).unwrap(), "~nobody_here");
+        assert_eq!(expand("a~", &Vars(home)).unwrap(), "a~");
+        assert_eq!(expand("'~'", &Vars(home)).unwrap(), "'~'");
+        assert_eq!(expand("\"~\"/x", &Vars(home)).unwrap(), "\"~\"/x");
+        assert_eq!(expand("~'x'", &Vars(home)).unwrap(), "~'x'");
+    }
+
+    #[test]
+    fn test_substitution() {
+        assert_eq!(expand("$(echo hi)", &Vars(status)).unwrap(), "[echo hi]");
+        assert_eq!(expand("a$(echo $(pwd))b", &Vars(status)).unwrap(), "a[echo $(pwd)]b");
+        assert_eq!(expand("\"$(echo 'x y')\"", &Vars(status)).unwrap(), "\"[echo 'x y']\"");
+        assert_eq!(expand("'$(echo hi)'", &Vars(status)).unwrap(), "'$(echo hi)'");
+        assert_eq!(expand("`echo \\`a\\``", &Vars(status)).unwrap(), "[echo `a`]");
+        assert_eq!(expand("`echo", &Vars(status)).unwrap(), "`echo");
+        assert_eq!(expand("$(echo", &Vars(status)).unwrap(), "$(echo");
+    }
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(split_fields("a b\tc\n"), ["a", "b", "c"]);
+        assert_eq!(split_fields("  a  "), ["a"]);
+        assert!(split_fields("").is_empty());
+        assert_eq!(split_fields("\"\""), ["\"\""]);
+        assert_eq!(split_fields("\"a b\" 'c d' e\\ f"), ["\"a b\"", "'c d'", "e\\ f"]);
+        assert_eq!(split_fields("x\"a b\"y z"), ["x\"a b\"y", "z"]);
+    }
+
+    #[test]
+    fn test_value_stays_literal() {
+        let value = |_: &str| Some("a \"b\" 'c' \\d".to_string());
+        assert_eq!(unescape(&expand("$?", &Vars(value)).unwrap()), "a \"b\" 'c' \\d");
+        assert_eq!(unescape(&expand("\"$?\"", &Vars(value)).unwrap()), "a \"b\" 'c' \\d");
+
+        let operator = |_: &str| Some("2>".to_string());
+        assert_eq!(expand("$?", &Vars(operator)).unwrap(), "2\\>");
+        assert_eq!(expand("\"$?\"", &Vars(operator)).unwrap(), "\"2>\"");
+    }
+}
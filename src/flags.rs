@@ -0,0 +1,138 @@
+use std::fmt;
+
+#[derive(PartialEq, Debug)]
+pub enum FlagError {
+    Invalid(char),
+    MissingValue(char),
+}
+
+impl fmt::Display for FlagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlagError::Invalid(flag) => write!(f, "-{}: invalid option", flag),
+            FlagError::MissingValue(flag) => write!(f, "-{}: option requires an argument", flag),
+        }
+    }
+}
+
+/**
+* what to do with a word containing a flag missing from the spec
+*/
+#[derive(PartialEq, Clone, Copy)]
+pub enum Unknown {
+    Error,
+    // the word and everything after it are operands, as `echo` does
+    Operand,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Parsed<'a> {
+    pub flags: Vec<(char, Option<String>)>,
+    pub operands: &'a [String],
+}
+
+/**
+* parse the leading short flags of `argv`, stopping at `--` or the first operand.
+* `spec` lists the known flags, a flag followed by `:` takes a value
+*/
+pub fn parse_flags<'a>(argv: &'a [String], spec: &str, unknown: Unknown) -> Result<Parsed<'a>, FlagError> {
+    let takes_value = |flag: char| match flag {
+        ':' => None,
+        _ => spec.find(flag).map(|i| spec[i + flag.len_utf8()..].starts_with(':')),
+    };
+    let mut flags = Vec::new();
+    let mut index = 0;
+
+    while let Some(arg) = argv.get(index) {
+        if arg == "--" {
+            index += 1;
+            break;
+        }
+        let Some(word) = arg.strip_prefix('-').filter(|w| !w.is_empty()) else {
+            break;
+        };
+        if unknown == Unknown::Operand && word.chars().any(|c| takes_value(c).is_none()) {
+            break;
+        }
+        index += 1;
+
+        for (i, flag) in word.char_indices() {
+            match takes_value(flag) {
+                None => return Err(FlagError::Invalid(flag)),
+                Some(false) => flags.push((flag, None)),
+                Some(true) => {
+                    let rest = &word[i + flag.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        rest.to_string()
+                    } else if let Some(value) = argv.get(index) {
+                        index += 1;
+                        value.to_string()
+                    } else {
+                        return Err(FlagError::MissingValue(flag));
+                    };
+                    flags.push((flag, Some(value)));
+                    break;
+                },
+            }
+        }
+    }
+
+    Ok(Parsed { flags, operands: &argv[index..] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_flags_and_operands() {
+        let argv = args(&["-ab", "-a", "x", "-b"]);
+        let parsed = parse_flags(&argv, "ab", Unknown::Error).unwrap();
+        assert_eq!(parsed.flags, vec![('a', None), ('b', None), ('a', None)]);
+        assert_eq!(parsed.operands, &argv[2..]);
+    }
+
+    #[test]
+    fn test_double_dash_and_single_dash() {
+        let argv = args(&["-a", "--", "-b"]);
+        let parsed = parse_flags(&argv, "ab", Unknown::Error).unwrap();
+        assert_eq!(parsed.flags, vec![('a', None)]);
+        assert_eq!(parsed.operands, &argv[2..]);
+
+        let argv = args(&["-"]);
+        let parsed = parse_flags(&argv, "ab", Unknown::Error).unwrap();
+        assert_eq!(parsed.operands, &argv[..]);
+    }
+
+    #[test]
+    fn test_values() {
+        let argv = args(&["-p", "path", "-ppath", "-ap", "x", "name"]);
+        let parsed = parse_flags(&argv, "ap:", Unknown::Error).unwrap();
+        assert_eq!(parsed.flags, vec![
+            ('p', Some("path".to_string())),
+            ('p', Some("path".to_string())),
+            ('a', None),
+            ('p', Some("x".to_string())),
+        ]);
+        assert_eq!(parsed.operands, &argv[5..]);
+
+        let argv = args(&["-p"]);
+        assert_eq!(parse_flags(&argv, "p:", Unknown::Error), Err(FlagError::MissingValue('p')));
+    }
+
+    #[test]
+    fn test_unknown() {
+        let argv = args(&["-a", "-ax", "b"]);
+        assert_eq!(parse_flags(&argv, "a", Unknown::Error), Err(FlagError::Invalid('x')));
+
+        let parsed = parse_flags(&argv, "a", Unknown::Operand).unwrap();
+        assert_eq!(parsed.flags, vec![('a', None)]);
+        assert_eq!(parsed.operands, &argv[1..]);
+
+        assert_eq!(FlagError::Invalid('x').to_string(), "-x: invalid option");
+    }
+}
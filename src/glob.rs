@@ -0,0 +1,310 @@
+use std::fs;
+use std::path::Path;
+
+/**
+* match `text` against a shell pattern.
+* `*` matches any string, `?` matches any single character,
+* `[...]` matches one character of a class
+* and a backslash makes the next character literal.
+*/
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_inside(&pattern, &text)
+}
+
+fn matches_inside(pattern: &[char], text: &[char]) -> bool {
+    let Some((p, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+    match p {
+        '*' => (0..=text.len()).any(|i| matches_inside(rest, &text[i..])),
+        '?' => !text.is_empty() && matches_inside(rest, &text[1..]),
+        '[' => match bracket(rest) {
+            Some((negate, class, after)) => {
+                text.first().is_some_and(|c| class_matches(class, *c) != negate) && matches_inside(after, &text[1..])
+            },
+            None => text.first() == Some(p) && matches_inside(rest, &text[1..]),
+        },
+        '\\' if !rest.is_empty() => {
+            text.first() == rest.first() && matches_inside(&rest[1..], &text[1..])
+        },
+        c => text.first() == Some(c) && matches_inside(rest, &text[1..]),
+    }
+}
+
+/**
+* split the class of a bracket expression from the rest of the pattern, after the `[`.
+* `!` or `^` first negates it, and a `]` right after that is part of the class
+*/
+fn bracket(pattern: &[char]) -> Option<(bool, &[char], &[char])> {
+    let negate = matches!(pattern.first(), Some('!' | '^'));
+    let start = negate as usize;
+    let mut index = start;
+    if pattern.get(index) == Some(&']') {
+        index += 1;
+    }
+    while let Some(c) = pattern.get(index) {
+        match c {
+            '\\' => index += 2,
+            ']' => return Some((negate, &pattern[start..index], &pattern[index + 1..])),
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+/**
+* whether `ch` is one of the characters or in one of the `a-z` ranges of `class`
+*/
+fn class_matches(class: &[char], ch: char) -> bool {
+    let mut index = 0;
+    let mut item = || {
+        let c = *class.get(index)?;
+        index += 1;
+        if c == '\\' {
+            let escaped = *class.get(index)?;
+            index += 1;
+            return Some((escaped, false));
+        }
+        Some((c, true))
+    };
+    let mut pending = item();
+    while let Some((lo, _)) = pending {
+        let next = item();
+        if let Some(('-', true)) = next {
+            match item() {
+                Some((hi, _)) => {
+                    if (lo..=hi).contains(&ch) {
+                        return true;
+                    }
+                    pending = item();
+                    continue;
+                },
+                // a `-` at the end is literal
+                None => return lo == ch || ch == '-',
+            }
+        }
+        if lo == ch {
+            return true;
+        }
+        pending = next;
+    }
+    false
+}
+
+fn push_literal(pattern: &mut String, ch: char) {
+    if matches!(ch, '*' | '?' | '[' | '\\') {
+        pattern.push('\\');
+    }
+    pattern.push(ch);
+}
+
+/**
+* the pattern a raw token stands for, with quoted characters escaped.
+* `None` when no wildcard is left unquoted, as nothing has to be matched then
+*/
+pub fn pattern(raw: &str) -> Option<String> {
+    let mut pattern = String::new();
+    let mut wildcard = false;
+    let mut quote: Option<char> = None;
+    let mut chars = raw.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (Some(q), _) if ch == q => quote = None,
+            (Some('\''), _) => push_literal(&mut pattern, ch),
+            (Some(_), '\\') => match chars.next_if(|c| matches!(c, '"' | '\\' | '$' | '`' | '\n')) {
+                Some(escaped) => push_literal(&mut pattern, escaped),
+                None => push_literal(&mut pattern, ch),
+            },
+            (Some(_), _) => push_literal(&mut pattern, ch),
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    push_literal(&mut pattern, escaped);
+                }
+            },
+            (None, '*' | '?' | '[') => {
+                wildcard = true;
+                pattern.push(ch);
+            },
+            (None, _) => pattern.push(ch),
+        }
+    }
+    wildcard.then_some(pattern)
+}
+
+fn has_wildcard(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            },
+            '*' | '?' | '[' => return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+/**
+* drop the escapes of a pattern without wildcards
+*/
+fn literal_text(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
+
+/**
+* the paths matching `pattern`, relative to `pwd` unless the pattern is absolute, sorted.
+* names starting with `.` only match a pattern starting with `.` unless `dotglob` is set
+*/
+pub fn expand(pwd: &Path, pattern: &str, dotglob: bool) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let dir_only = rest.ends_with('/');
+    let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+
+    for (index, component) in components.iter().enumerate() {
+        let last = index + 1 == components.len();
+        let separator = if last { "" } else { "/" };
+        let mut next = Vec::new();
+        for prefix in &paths {
+            if !has_wildcard(component) {
+                next.push(format!("{}{}{}", prefix, literal_text(component), separator));
+                continue;
+            }
+            let Ok(entries) = fs::read_dir(pwd.join(prefix)) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| dotglob || !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| matches(component, name))
+                .collect();
+            names.sort();
+            for name in names {
+                let path = format!("{}{}", prefix, name);
+                if last || pwd.join(&path).is_dir() {
+                    next.push(format!("{}{}", path, separator));
+                }
+            }
+        }
+        paths = next;
+    }
+
+    let mut paths: Vec<String> = paths.into_iter()
+        .filter(|path| fs::symlink_metadata(pwd.join(path)).is_ok())
+        .filter(|path| !dir_only || pwd.join(path).is_dir())
+        .map(|path| if dir_only { format!("{}/", path) } else { path })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal() {
+        assert!(matches("echo", "echo"));
+        assert!(!matches("echo", "ech"));
+        assert!(!matches("echo", "echoo"));
+    }
+
+    #[test]
+    fn test_star() {
+        assert!(matches("ec*", "echo"));
+        assert!(matches("*", ""));
+        assert!(matches("*o", "echo"));
+        assert!(matches("e*h*", "echo"));
+        assert!(!matches("x*", "echo"));
+    }
+
+    #[test]
+    fn test_question() {
+        assert!(matches("e?ho", "echo"));
+        assert!(matches("??", "cd"));
+        assert!(!matches("?", ""));
+        assert!(!matches("???", "cd"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert!(matches(r"a\*", "a*"));
+        assert!(!matches(r"a\*", "ab"));
+    }
+
+    #[test]
+    fn test_bracket() {
+        assert!(matches("[fb]oo.txt", "foo.txt"));
+        assert!(matches("[fb]oo.txt", "boo.txt"));
+        assert!(!matches("[fb]oo.txt", "zoo.txt"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[0-9a-f][0-9a-f]", "3e"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(!matches("[^a-c]", "a"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[!]]", "a"));
+        assert!(!matches("[!]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches(r"[\]]", "]"));
+        assert!(matches("[", "["));
+        assert!(matches("[ab", "[ab"));
+        assert!(!matches("[ab]", ""));
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern("[ab]'['"), Some(r"[ab]\[".to_string()));
+        assert_eq!(pattern("*.rs"), Some("*.rs".to_string()));
+        assert_eq!(pattern("a'*'?"), Some(r"a\*?".to_string()));
+        assert_eq!(pattern("\"a?\"*"), Some(r"a\?*".to_string()));
+        assert_eq!(pattern(r"\**"), Some(r"\**".to_string()));
+        assert_eq!(pattern("'*'"), None);
+        assert_eq!(pattern(r"\?"), None);
+        assert_eq!(pattern("plain"), None);
+    }
+
+    fn glob_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("shell-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for file in ["main.rs", "lib.rs", "notes.txt", ".hidden.rs", "src/a.rs", "foo.txt", "boo.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = glob_dir("glob");
+        assert_eq!(expand(&dir, "*.rs", false), ["lib.rs", "main.rs"]);
+        assert_eq!(expand(&dir, "*.rs", true), [".hidden.rs", "lib.rs", "main.rs"]);
+        assert_eq!(expand(&dir, ".*.rs", false), [".hidden.rs"]);
+        assert_eq!(expand(&dir, "????.rs", false), ["main.rs"]);
+        assert_eq!(expand(&dir, "*/*.rs", false), ["src/a.rs"]);
+        assert_eq!(expand(&dir, "[fb]oo.txt", false), ["boo.txt", "foo.txt"]);
+        assert_eq!(expand(&dir, "[!fb]*.txt", false), ["notes.txt"]);
+        assert_eq!(expand(&dir, "src/?.rs", false), ["src/a.rs"]);
+        assert_eq!(expand(&dir, "*/", false), ["src/"]);
+        assert!(expand(&dir, "*.none", false).is_empty());
+        assert!(expand(&dir, "missing/*", false).is_empty());
+
+        let absolute = format!("{}/n*.txt", dir.display());
+        assert_eq!(expand(&dir, &absolute, false), [format!("{}/notes.txt", dir.display())]);
+    }
+}
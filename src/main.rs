@@ -1,150 +1,436 @@
+use crate::expand::{expand, is_name, literal, split_fields, Context};
+use crate::flags::{parse_flags, Unknown};
 use crate::tokenize::ParseError;
-use crate::tokenize::tokenize;
+use crate::tokenize::{continues_line, tokenize};
 use crate::unescape::unescape;
+use crate::options::{ShellOptions, ShoptOptions};
 use std::env;
 use std::fs::File;
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::{fs, sync::LazyLock};
-use std::collections::HashMap;
-use std::process::Command;
-#[allow(unused_imports)]
-use std::io::{self, Write};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::os::fd::AsFd;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 
+mod arith;
+mod editor;
+mod escapes;
+mod expand;
+mod flags;
+mod glob;
+mod options;
+mod signals;
+mod sys;
 mod tokenize;
 mod unescape;
 
 type ExitCode = i32;
 
-type BuiltinFunction = fn(ShellState, &[String], Box<dyn Write>)->ShellState;
+type BuiltinFunction = fn(ShellState, &[String], Box<dyn Write>, Box<dyn Write>)->ShellState;
 
+struct Builtin {
+    func: BuiltinFunction,
+    synopsis: &'static str,
+    description: &'static str,
+}
+
+#[derive(Clone)]
+struct HashEntry {
+    path: PathBuf,
+    hits: usize,
+}
+
+/**
+* locations of commands already found in PATH
+*/
+#[derive(Default, Clone)]
+struct CommandHash {
+    path: String,
+    entries: HashMap<String, HashEntry>,
+}
+impl CommandHash {
+    /**
+    * find `cmd` without counting a hit, scanning `path` only on a miss.
+    * the cache is dropped whenever `path` differs from the one it was built for
+    */
+    fn find(&mut self, path: &str, cmd: &str) -> Option<&mut HashEntry> {
+        self.sync_path(path);
+        if self.entries.get(cmd).is_some_and(|entry| !entry.path.is_file()) {
+            self.entries.remove(cmd);
+        }
+        if !self.entries.contains_key(cmd) {
+            let found = which_internal(path, cmd)?;
+            self.entries.insert(cmd.to_string(), HashEntry { path: found, hits: 0 });
+        }
+        self.entries.get_mut(cmd)
+    }
+
+    fn sync_path(&mut self, path: &str) {
+        if self.path != path {
+            self.entries.clear();
+            self.path = path.to_string();
+        }
+    }
+
+    /**
+    * find `cmd` for execution and count the hit
+    */
+    fn lookup(&mut self, path: &str, cmd: &str) -> Option<PathBuf> {
+        let entry = self.find(path, cmd)?;
+        entry.hits += 1;
+        Some(entry.path.clone())
+    }
+
+    fn sorted(&self) -> Vec<(&String, &HashEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+/**
+* the logical working directory: `$PWD` when it names the current directory
+*/
+fn initial_pwd() -> PathBuf {
+    let cwd = env::current_dir().unwrap();
+    let logical = env::var("PWD").ok().map(PathBuf::from).filter(|pwd| {
+        pwd.is_absolute() && fs::canonicalize(pwd).ok() == fs::canonicalize(&cwd).ok()
+    });
+    logical.unwrap_or(cwd)
+}
+
+/**
+* a rendered prompt with everything it was rendered from
+*/
+#[derive(Clone, PartialEq, Debug)]
+struct CachedPrompt {
+    inputs: (String, PathBuf, Option<String>, Option<String>),
+    rendered: String,
+}
+
+/**
+* a pipeline left running in the background
+*/
+#[derive(Clone, PartialEq, Debug)]
+struct Job {
+    number: usize,
+    pids: Vec<u32>,
+    command: String,
+}
+
+/**
+* a script being run, with the line its current command starts on
+*/
+#[derive(Clone, PartialEq, Debug)]
+struct SourceFrame {
+    file: String,
+    line: usize,
+}
+
+#[derive(Clone)]
 struct ShellState {
     exit_code: Option<ExitCode>,
-    pwd: PathBuf
+    last_status: ExitCode,
+    // status before the running builtin, which `exit` falls back to
+    previous_status: ExitCode,
+    pwd: PathBuf,
+    shopt: ShoptOptions,
+    options: ShellOptions,
+    command_hash: CommandHash,
+    audit_log_disabled: bool,
+    interactive: bool,
+    debug: bool,
+    stopwatch: Option<Instant>,
+    // shell variables, which shadow the process environment
+    variables: HashMap<String, String>,
+    exported: HashSet<String>,
+    readonly: HashSet<String>,
+    // variables of the process environment which were unset, hidden from lookups and commands
+    unset: HashSet<String>,
+    // the directory `cd` last left, which `cd -` returns to
+    oldpwd: Option<PathBuf>,
+    // directories left by `cd`, most recent first
+    dir_history: VecDeque<PathBuf>,
+    // directories saved by `pushd`, the most recent last
+    dir_stack: Vec<PathBuf>,
+    // pipelines started with `&` that have not been reaped yet
+    jobs: Vec<Job>,
+    // prompts without expansions by variable name, see `render_prompt`
+    prompt_cache: RefCell<HashMap<String, CachedPrompt>>,
+    // command lines read so far, oldest first
+    history: Vec<String>,
+    // how many of the last history entries were read in this session
+    history_new: usize,
+    // where standard output goes instead of the shell's own, as in `$(...)`
+    stdout: Option<Rc<File>>,
+    // where a builtin reads its standard input from, when it is redirected
+    stdin: Option<Rc<File>>,
+    // the scripts being run, the innermost last
+    sources: Vec<SourceFrame>,
+    // `$0` followed by the positional parameters
+    args: Vec<String>,
 }
 impl ShellState {
     fn default() -> ShellState {
         ShellState {
             exit_code: None,
-            pwd: env::current_dir().unwrap()
+            last_status: 0,
+            previous_status: 0,
+            pwd: initial_pwd(),
+            shopt: ShoptOptions::default(),
+            options: ShellOptions::default(),
+            command_hash: CommandHash::default(),
+            audit_log_disabled: false,
+            interactive: false,
+            debug: false,
+            stopwatch: None,
+            variables: HashMap::new(),
+            exported: HashSet::new(),
+            readonly: HashSet::new(),
+            unset: HashSet::new(),
+            oldpwd: None,
+            dir_history: VecDeque::new(),
+            dir_stack: Vec::new(),
+            jobs: Vec::new(),
+            prompt_cache: RefCell::new(HashMap::new()),
+            history: Vec::new(),
+            history_new: 0,
+            stdout: None,
+            stdin: None,
+            sources: Vec::new(),
+            args: Vec::new(),
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 enum RedirMode {
+    Read,
     Write,
     Append
 }
 
-struct Proc<'a> {
-    exec: &'a str,
+#[derive(PartialEq, Debug, Clone)]
+enum RedirTarget {
+    File(String, RedirMode),
+    Fd(usize),
+}
+
+/**
+* redirection of the file descriptor `fd`, applied left to right
+*/
+#[derive(PartialEq, Debug, Clone)]
+struct Redirect {
+    fd: usize,
+    target: RedirTarget,
+}
+
+struct Proc {
+    // empty when the command only assigns variables
+    exec: String,
     argv: Vec<String>,
-    stdout: Option<&'a str>,
-    stdout_mode: RedirMode,
-    stderr: Option<&'a str>,
-    stderr_mode: RedirMode,
+    redirects: Vec<Redirect>,
+    // `NAME=value` words before the command, only set in its environment
+    assignments: Vec<(String, String)>,
+}
+
+/**
+* print the words after an expansion stage to stderr when debugging,
+* as `--debug` or a non-empty `SHELL_DEBUG` asks
+*/
+fn trace<S: AsRef<str>>(state: &ShellState, stage: &str, words: &[S]) {
+    if !state.debug && !variable(state, "SHELL_DEBUG").is_some_and(|v| !v.is_empty()) {
+        return;
+    }
+    let words: Vec<&str> = words.iter().map(|w| w.as_ref()).collect();
+    eprintln!("+ {}: {:?}", stage, words);
+}
+
+fn parse(state: &ShellState, src: &str) -> Result<Vec<String>, ParseError> {
+    let tokens = tokenize(src)?;
+    trace(state, "tokens", &tokens);
+    Ok(tokens.iter().map(|s| s.to_string()).collect())
 }
 
-fn parse(src: &str) -> Result<Vec<String>, ParseError> {
-    tokenize(src).map(|tokens| tokens.iter().map(|s| unescape(s)).collect())
+/**
+* split a redirection token into the file descriptor written before it and the operator
+*/
+fn redirect_operator(token: &str) -> Option<(Option<usize>, &str)> {
+    let (fd, op) = match token.chars().next()?.to_digit(10) {
+        Some(fd) => (Some(fd as usize), &token[1..]),
+        None => (None, token),
+    };
+    matches!(op, "<" | ">" | ">>" | ">&").then_some((fd, op))
 }
 
-enum ToRedirect {
-    Stdout,
-    Stderr,
+/**
+* split a raw `NAME=value` word into the name and the unescaped value
+*/
+fn assignment(token: &str) -> Option<(String, String)> {
+    let (name, value) = token.split_once('=')?;
+    is_name(name).then(|| (name.to_string(), unescape(value)))
 }
 
-fn words2proc(argv: &[String]) -> Option<Proc<'_>> {
-    let exec = argv.first()?;
-    let mut cursor = argv[1..].iter().enumerate().peekable();
-    let mut to_redirect: Option<ToRedirect> = None;
-    
+/**
+* build a process from the tokens of a single command.
+* operators are recognized on the raw tokens so quoted ones stay words
+*/
+fn words2proc(tokens: &[String]) -> Option<Proc> {
+    if tokens.is_empty() {
+        return None;
+    }
+    let mut assignments = Vec::new();
+    let mut tokens = tokens;
+    while let Some(pair) = tokens.first().and_then(|token| assignment(token)) {
+        assignments.push(pair);
+        tokens = &tokens[1..];
+    }
+    let exec = tokens.first().map(|token| unescape(token)).unwrap_or_default();
+    let mut cursor = tokens.iter().skip(1);
+
     let mut proc = Proc {
         exec,
         argv: Vec::<String>::new(),
-        stdout: None,
-        stdout_mode: RedirMode::Write,
-        stderr: None,
-        stderr_mode: RedirMode::Write,
-    };
-
-    while let Some((_index, word)) = cursor.next() {
-        if word == "1" || word == "2" {
-            let next = cursor.peek();
-            let next_is_redirect = next.filter(|(_, w)| *w == ">" || *w == ">>").is_some();
-            if next_is_redirect {
-                to_redirect = match word.as_str() {
-                    "1" => Some(ToRedirect::Stdout),
-                    "2" => Some(ToRedirect::Stderr),
-                    _ => panic!()
-                };
+        redirects: Vec::new(),
+        assignments,
+    };
+
+    while let Some(word) = cursor.next() {
+        let Some((explicit_fd, op)) = redirect_operator(word) else {
+            proc.argv.push(unescape(word));
+            continue;
+        };
+        let mode = match op {
+            "<" => RedirMode::Read,
+            ">>" => RedirMode::Append,
+            _ => RedirMode::Write,
+        };
+        let target = cursor.next()?;
+        let default_fd = if mode == RedirMode::Read { 0 } else { 1 };
+        if op == ">&" {
+            if let Ok(to) = target.parse::<usize>() {
+                proc.redirects.push(Redirect { fd: explicit_fd.unwrap_or(1), target: RedirTarget::Fd(to) });
                 continue;
             }
-        } else if word == ">" {
-            let target = cursor.next().unwrap().1;
-            match to_redirect.as_ref().unwrap_or(&ToRedirect::Stdout) {
-                ToRedirect::Stdout => {
-                    proc.stdout = Some(target);
-                    proc.stdout_mode = RedirMode::Write;
-                },
-                ToRedirect::Stderr => {
-                    proc.stderr = Some(target);
-                    proc.stderr_mode = RedirMode::Write;
-                },
-            }
-            continue;
-        }
-
-        if word == ">>" {
-            let target = cursor.next().unwrap().1;
-            match to_redirect.as_ref().unwrap_or(&ToRedirect::Stdout) {
-                ToRedirect::Stdout => {
-                    proc.stdout = Some(target);
-                    proc.stdout_mode = RedirMode::Append;
-                },
-                ToRedirect::Stderr => {
-                    proc.stderr = Some(target);
-                    proc.stderr_mode = RedirMode::Append;
-                },
+            // `>& file` sends both stdout and stderr to the file
+            if explicit_fd.is_some() {
+                return None;
             }
+            proc.redirects.push(Redirect { fd: 1, target: RedirTarget::File(unescape(target), mode) });
+            proc.redirects.push(Redirect { fd: 2, target: RedirTarget::Fd(1) });
             continue;
         }
-
-        proc.argv.push(word.to_string());
+        proc.redirects.push(Redirect {
+            fd: explicit_fd.unwrap_or(default_fd),
+            target: RedirTarget::File(unescape(target), mode),
+        });
     }
 
     Some(proc)
 }
 
-fn echo(state: ShellState, argv: &[String], mut stdout: Box<dyn Write>) -> ShellState {
-    let messages = argv.join(" ");
-    stdout.write_all(format!("{}\n", messages).as_bytes()).expect("should success to write");
+/**
+* split the tokens on `|` into the processes of a pipeline.
+* a malformed command yields the token the error was found at
+*/
+fn words2pipeline(tokens: &[String]) -> Result<Vec<Proc>, &'static str> {
+    tokens.split(|token| token == "|").map(|stage| {
+        if stage.is_empty() {
+            Err("|")
+        } else {
+            words2proc(stage).ok_or("newline")
+        }
+    }).collect()
+}
+
+/**
+* report a failure of the builtin `name` on its standard error `out` as `shell: name: message`
+*/
+fn builtin_error(out: &mut dyn Write, name: &str, message: impl fmt::Display) {
+    let _ = writeln!(out, "shell: {}: {}", name, message);
+}
+
+fn echo(state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, _: Box<dyn Write>) -> ShellState {
+    let mut newline = true;
+    let mut interpret = false;
+    let parsed = parse_flags(argv, "neE", Unknown::Operand).expect("unknown flags are operands");
+    for (flag, _) in parsed.flags {
+        match flag {
+            'n' => newline = false,
+            'e' => interpret = true,
+            _ => interpret = false,
+        }
+    }
+    let operands = parsed.operands;
+
+    let mut messages = operands.join(" ");
+    if interpret {
+        let (decoded, stopped) = escapes::decode(&messages);
+        messages = decoded;
+        // `\c` drops the rest of the output including the newline
+        newline &= !stopped;
+    }
+    if newline {
+        messages.push('\n');
+    }
+    stdout.write_all(messages.as_bytes()).expect("should success to write");
     state
 }
 
-fn exit(mut state: ShellState, argv: &[String], _: Box<dyn Write>) -> ShellState {
-    let code = argv.first().map(|v| v.parse::<ExitCode>()).unwrap_or(Ok(0));
-    if let Err(e) = code {
-        println!("{}", e);
+fn exit(mut state: ShellState, argv: &[String], _: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let code = argv.first().map(|v| v.parse::<ExitCode>()).unwrap_or(Ok(state.previous_status));
+    if code.is_err() {
+        builtin_error(&mut stderr, "exit", format_args!("{}: numeric argument required", argv[0]));
+        state.last_status = 2;
     } else if let Ok(code) = code {
         state.exit_code = Some(code);
     }
     state
 }
 
-fn type_fn(state: ShellState, argv: &[String], _: Box<dyn Write>) -> ShellState {
+#[derive(PartialEq, Debug)]
+enum Resolution {
+    Builtin,
+    File(PathBuf),
+    NotFound,
+}
+
+/**
+* what running `name` as a command would execute
+*/
+fn resolve(path: &str, name: &str) -> Resolution {
+    if BUILTIN_FUNCITONS.contains_key(name) {
+        Resolution::Builtin
+    } else if let Some(path) = which_internal(path, name) {
+        Resolution::File(path)
+    } else {
+        Resolution::NotFound
+    }
+}
+
+fn type_fn(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
     let Some(cmd) = argv.first() else {
-        println!("type [cmd]");
+        builtin_error(&mut stderr, "type", "usage: type [cmd]");
+        state.last_status = 2;
         return state
     };
-    if BUILTIN_FUNCITONS.get((*cmd).as_str()).is_some() {
-        println!("{} is a shell builtin", cmd);
-    } else if let Some(cmd_ext) = which_internal(&std::env::var("PATH").unwrap_or("".to_string()), cmd) {
-        println!("{} is {}", cmd, cmd_ext.display());
-    } else {
-        println!("{}: not found", cmd);
-    }
+    let message = match resolve(&variable(&state, "PATH").unwrap_or_default(), cmd) {
+        Resolution::Builtin => format!("{} is a shell builtin\n", cmd),
+        Resolution::File(path) => format!("{} is {}\n", cmd, path.display()),
+        Resolution::NotFound => {
+            builtin_error(&mut stderr, "type", format_args!("{}: not found", cmd));
+            state.last_status = 1;
+            return state;
+        },
+    };
+    stdout.write_all(message.as_bytes()).expect("should success to write");
     state
 }
 
@@ -155,236 +441,3281 @@ fn which_internal(path: &str, cmd: &str) -> Option<PathBuf> {
         let Ok(metadata) = fs::metadata(path.clone()) else {
             continue;
         };
-        if metadata.is_file() && (metadata.permissions().mode() & 0o111 != 0) {
+        if metadata.is_file() && sys::is_executable(&path) {
             return Some(path)
         }
     }
     None
 }
 
-fn which(state: ShellState, argv: &[String], _: Box<dyn Write>) -> ShellState {
-    let Some(cmd) = argv.first() else {
-        println!("which [cmd]");
+/**
+* the builtins and commands on `PATH` whose names start with `prefix`, sorted
+*/
+fn command_candidates(state: &ShellState, prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_FUNCITONS.keys()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    let path = variable(state, "PATH").unwrap_or_default();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            if name.starts_with(prefix) && path.is_file() && sys::is_executable(&path) {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn which(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let operands = match parse_flags(argv, "", Unknown::Error) {
+        Ok(parsed) => parsed.operands,
+        Err(e) => {
+            builtin_error(&mut stderr, "which", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+    let Some(cmd) = operands.first() else {
+        builtin_error(&mut stderr, "which", "usage: which [cmd]");
+        state.last_status = 2;
         return state
     };
-    match which_internal(&std::env::var("PATH").unwrap_or("".to_string()), cmd) {
+    let message = match which_internal(&variable(&state, "PATH").unwrap_or_default(), cmd) {
         None => {
-            println!("{}: not found", cmd);
-        }
-        Some(cmd_full) => {
-            println!("{} is {}", cmd, cmd_full.as_path().display());
-        }
+            builtin_error(&mut stderr, "which", format_args!("{}: not found", cmd));
+            state.last_status = 1;
+            return state;
+        },
+        Some(cmd_full) => format!("{} is {}\n", cmd, cmd_full.display()),
     };
+    stdout.write_all(message.as_bytes()).expect("should success to write");
     state
 }
 
-fn pwd(state: ShellState, _argv: &[String], _: Box<dyn Write>) -> ShellState {
-    println!("{}", state.pwd.display());
+fn pwd(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let mut physical = false;
+    let parsed = match parse_flags(argv, "LP", Unknown::Error) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            builtin_error(&mut stderr, "pwd", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+    for (flag, _) in parsed.flags {
+        physical = flag == 'P';
+    }
+    let dir = if physical {
+        fs::canonicalize(&state.pwd).unwrap_or(state.pwd.clone())
+    } else {
+        state.pwd.clone()
+    };
+    stdout.write_all(format!("{}\n", dir.display()).as_bytes()).expect("should success to write");
     state
 }
 
-fn cd(mut state: ShellState, argv: &[String], _: Box<dyn Write>) -> ShellState {
+/**
+* resolve `.` and `..` without following symlinks
+*/
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+const DIR_HISTORY_SIZE: usize = 16;
+
+/**
+* the directory `-N` or `+N` refers to in the `cd` history.
+* `-N` counts back from the most recent directory and `+N` forward from the oldest
+*/
+fn dir_history_target(state: &ShellState, arg: &str) -> Option<Result<PathBuf, String>> {
+    if arg == "-" {
+        return Some(state.oldpwd.clone().ok_or("OLDPWD not set".to_string()));
+    }
+    let (from_oldest, index) = match arg.strip_prefix('-') {
+        Some(index) => (false, index),
+        None => (true, arg.strip_prefix('+')?),
+    };
+    let index = index.parse::<usize>().ok()?;
+    let entry = match index.checked_sub(1) {
+        Some(i) if from_oldest => state.dir_history.len().checked_sub(i + 1).and_then(|i| state.dir_history.get(i)),
+        Some(i) => state.dir_history.get(i),
+        None => None,
+    };
+    Some(entry.cloned().ok_or(format!("{}: directory stack index out of range", arg)))
+}
+
+/**
+* make `path` the working directory, remembering the one left
+*/
+fn enter_dir(state: &mut ShellState, path: PathBuf) {
+    let previous = std::mem::replace(&mut state.pwd, path);
+    state.variables.insert("OLDPWD".to_string(), previous.display().to_string());
+    state.exported.insert("OLDPWD".to_string());
+    state.oldpwd = Some(previous.clone());
+    state.dir_history.push_front(previous);
+    state.dir_history.truncate(DIR_HISTORY_SIZE);
+}
+
+fn cd(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let mut print_target = false;
     let new_wd = match argv.first() {
         None => {
             env::home_dir()
         }
         Some(dir) => {
-            if dir == "~" {
-                env::home_dir()
+            if let Some(target) = dir_history_target(&state, dir) {
+                match target {
+                    Ok(path) => {
+                        print_target = true;
+                        Some(path)
+                    },
+                    Err(message) => {
+                        builtin_error(&mut stderr, "cd", message);
+                        state.last_status = 1;
+                        return state;
+                    }
+                }
             } else {
                 Some(PathBuf::from(dir))
             }
         }
     };
     let Some(new_wd) = new_wd else {
-        println!("failed to get new directory");
+        builtin_error(&mut stderr, "cd", "HOME not set");
+        state.last_status = 1;
         return state;
     };
 
-    match fs::canonicalize(state.pwd.join(new_wd.clone())) {
-        Ok(path) => {
-            state.pwd = path;
+    let path = normalize_path(&state.pwd.join(&new_wd));
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => {
+            if print_target {
+                stdout.write_all(format!("{}\n", path.display()).as_bytes()).expect("should success to write");
+            }
+            enter_dir(&mut state, path);
+        },
+        Ok(_) => {
+            builtin_error(&mut stderr, "cd", format_args!("{}: Not a directory", new_wd.display()));
+            state.last_status = 1;
         },
         Err(e) => {
-            if e.kind() == io::ErrorKind::NotFound {
-                println!("cd: {}: No such file or directory", new_wd.display());
-            } else {
-                println!("Unexpected error: {}, {:?}", e, e.kind());
-            }
+            builtin_error(&mut stderr, "cd", format_args!("{}: {}", new_wd.display(), io_error_message(&e)));
+            state.last_status = 1;
         }
     }
     state
 }
 
-static BUILTIN_FUNCITONS: LazyLock<HashMap<&str, BuiltinFunction>> = LazyLock::new(|| -> HashMap<&str, BuiltinFunction> {
-    let mut map = HashMap::new();
-    map.insert("echo", echo as BuiltinFunction);
-    map.insert("exit", exit as BuiltinFunction);
-    map.insert("type", type_fn as BuiltinFunction);
-    map.insert("which", which as BuiltinFunction);
-    map.insert("pwd", pwd as BuiltinFunction);
-    map.insert("cd", cd as BuiltinFunction);
-    map
-});
+/**
+* `path` with the home directory shown as `~`
+*/
+fn abbreviate_home(state: &ShellState, path: &Path) -> String {
+    let home = variable(state, "HOME").filter(|home| !home.is_empty());
+    match home.and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
 
-fn main() {
-    let stdin = io::stdin();
-    let mut state = ShellState::default();
+fn print_dir_stack(state: &ShellState, stdout: &mut dyn Write) {
+    let dirs: Vec<String> = std::iter::once(&state.pwd)
+        .chain(state.dir_stack.iter().rev())
+        .map(|dir| abbreviate_home(state, dir))
+        .collect();
+    stdout.write_all(format!("{}\n", dirs.join(" ")).as_bytes()).expect("should success to write");
+}
 
-    // Wait for user input
-    while state.exit_code.is_none() {
-        print!("$ ");
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        stdin.read_line(&mut input).unwrap();
-        match parse(&input) {
-            Ok(argv) => {
-                state = eval(state, &argv);
+fn pushd(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let target = match argv.first() {
+        Some(dir) => normalize_path(&state.pwd.join(dir)),
+        None => match state.dir_stack.pop() {
+            Some(dir) => dir,
+            None => {
+                builtin_error(&mut stderr, "pushd", "no other directory");
+                state.last_status = 1;
+                return state;
             },
-            Err(e) => {
-                println!("{:?}", e);
-            }
+        },
+    };
+    if !target.is_dir() {
+        let name = argv.first().cloned().unwrap_or_else(|| target.display().to_string());
+        builtin_error(&mut stderr, "pushd", format_args!("{}: No such file or directory", name));
+        state.last_status = 1;
+        return state;
+    }
+    let current = state.pwd.clone();
+    enter_dir(&mut state, target);
+    state.dir_stack.push(current);
+    print_dir_stack(&state, &mut stdout);
+    state
+}
+
+fn popd(mut state: ShellState, _argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let Some(target) = state.dir_stack.pop() else {
+        builtin_error(&mut stderr, "popd", "directory stack empty");
+        state.last_status = 1;
+        return state;
+    };
+    if !target.is_dir() {
+        builtin_error(&mut stderr, "popd", format_args!("{}: No such file or directory", target.display()));
+        state.last_status = 1;
+        return state;
+    }
+    enter_dir(&mut state, target);
+    print_dir_stack(&state, &mut stdout);
+    state
+}
+
+fn dirs(state: ShellState, _argv: &[String], mut stdout: Box<dyn Write>, _: Box<dyn Write>) -> ShellState {
+    print_dir_stack(&state, &mut stdout);
+    state
+}
+
+fn shopt(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let mut set: Option<bool> = None;
+    let mut quiet = false;
+    let mut names = Vec::<&str>::new();
+    for arg in argv {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-q" => quiet = true,
+            name => names.push(name),
         }
     }
-    std::process::exit(state.exit_code.unwrap());
-}
-
-fn eval(state: ShellState, argv: &[String]) -> ShellState{
-    let proc = words2proc(argv);
-    match proc {
-        None => state,
-        Some(proc) => {
-            if let Some(builtin_fn) = BUILTIN_FUNCITONS.get(proc.exec) {
-                let stdout: Box<dyn Write> = match proc.stdout {
-                    None => Box::new(std::io::stdout()),
-                    Some(filename) => {
-                        let filename = state.pwd.join(filename);
-                        match proc.stdout_mode {
-                            RedirMode::Write => Box::new(File::create(filename).unwrap()),
-                            RedirMode::Append => Box::new(File::options()
-                                .create(true)
-                                .append(true)
-                                .open(filename)
-                                .unwrap())
-                        }
 
-                    }
-                };
-                let _stderr: Box<dyn Write> = match proc.stderr {
-                    None => Box::new(std::io::stderr()),
-                    Some(filename) => {
-                        let filename = state.pwd.join(filename);
-                        match proc.stderr_mode {
-                            RedirMode::Write => Box::new(File::create(filename).unwrap()),
-                            RedirMode::Append => Box::new(File::options()
-                                .create(true)
-                                .append(true)
-                                .open(filename)
-                                .unwrap())
-                        }
+    if names.is_empty() {
+        for name in ShoptOptions::NAMES {
+            let value = state.shopt.get(name).unwrap();
+            if quiet || set.is_some_and(|set| set != value) {
+                continue;
+            }
+            let line = format!("{:<15}\t{}\n", name, if value { "on" } else { "off" });
+            stdout.write_all(line.as_bytes()).expect("should success to write");
+        }
+        return state;
+    }
 
-                    }
-                };
-                builtin_fn(state, &proc.argv, stdout)
-            } else if which_internal(&std::env::var("PATH").unwrap_or("".to_string()), proc.exec).is_some() {
-                let mut cmd = Command::new(proc.exec);
-                cmd.args(proc.argv)
-                    .current_dir(state.pwd.clone());
-                if let Some(stdout) = proc.stdout {
-                    let filename = state.pwd.join(stdout);
-                    let f = match proc.stdout_mode {
-                        RedirMode::Write => File::create(filename).unwrap(),
-                        RedirMode::Append => File::options()
-                            .create(true)
-                            .append(true)
-                            .open(filename)
-                            .unwrap()
-                    };
-                    cmd.stdout(f);
+    for name in names {
+        let Some(value) = state.shopt.get_mut(name) else {
+            builtin_error(&mut stderr, "shopt", format_args!("{}: invalid shell option name", name));
+            state.last_status = 1;
+            continue;
+        };
+        match set {
+            Some(set) => *value = set,
+            None => {
+                // querying fails unless every option named is on
+                let on = *value;
+                if !quiet {
+                    let line = format!("{:<15}\t{}\n", name, if on { "on" } else { "off" });
+                    stdout.write_all(line.as_bytes()).expect("should success to write");
                 }
-                if let Some(stderr) = proc.stderr {
-                    let filename = state.pwd.join(stderr);
-                    let f = match proc.stderr_mode {
-                        RedirMode::Write => File::create(filename).unwrap(),
-                        RedirMode::Append => File::options()
-                            .create(true)
-                            .append(true)
-                            .open(filename)
-                            .unwrap()
-                    };
-                    cmd.stderr(f);
+                if !on {
+                    state.last_status = 1;
                 }
+            },
+        }
+    }
+    state
+}
 
-                let _ = cmd
-                    .spawn()
-                    .expect("")
-                    .wait()
-                    ;
-                state
-            } else {
-                println!("{}: command not found", proc.exec);
-                state
+fn set(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let mut operands = argv.iter();
+    while let Some(arg) = operands.next() {
+        let (enable, flags) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+            (Some(flags), _) => (true, flags),
+            (_, Some(flags)) => (false, flags),
+            _ => {
+                builtin_error(&mut stderr, "set", format_args!("{}: invalid option", arg));
+                state.last_status = 2;
+                return state;
+            },
+        };
+        for flag in flags.chars() {
+            let name = match flag {
+                'o' => match operands.next() {
+                    Some(name) => name.as_str(),
+                    None => {
+                        for (name, _) in ShellOptions::NAMES {
+                            let value = state.options.get(name).unwrap();
+                            let line = format!("{:<15}\t{}\n", name, if value { "on" } else { "off" });
+                            stdout.write_all(line.as_bytes()).expect("should success to write");
+                        }
+                        continue;
+                    },
+                },
+                flag => match ShellOptions::name_of(flag) {
+                    Some(name) => name,
+                    None => {
+                        builtin_error(&mut stderr, "set", format_args!("{}{}: invalid option", &arg[..1], flag));
+                        state.last_status = 2;
+                        return state;
+                    },
+                },
+            };
+            match state.options.get_mut(name) {
+                Some(value) => *value = enable,
+                None => {
+                    builtin_error(&mut stderr, "set", format_args!("{}: invalid option name", name));
+                    state.last_status = 2;
+                    return state;
+                },
             }
         }
     }
+    state
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+const DEFAULT_HISTSIZE: usize = 500;
 
-    fn args(a: &[&str]) -> Vec<String> {
-        a.iter().map(|a| a.to_string()).collect()
+fn history_size(state: &ShellState) -> usize {
+    variable(state, "HISTSIZE").and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_HISTSIZE)
+}
+
+fn history_file(state: &ShellState) -> Option<PathBuf> {
+    let home = variable(state, "HOME").filter(|home| !home.is_empty())?;
+    Some(Path::new(&home).join(".shell_history"))
+}
+
+/**
+* keep only the last `size` lines
+*/
+fn trim_history(lines: &mut Vec<String>, size: usize) {
+    let excess = lines.len().saturating_sub(size);
+    lines.drain(..excess);
+}
+
+fn add_history(state: &mut ShellState, line: &str) {
+    state.history.push(line.to_string());
+    state.history_new += 1;
+    let size = history_size(state);
+    trim_history(&mut state.history, size);
+    state.history_new = state.history_new.min(state.history.len());
+}
+
+/**
+* start from the history file, or from nothing when it cannot be read
+*/
+fn load_history(state: &mut ShellState) {
+    let Some(content) = history_file(state).and_then(|path| fs::read_to_string(path).ok()) else {
+        return;
+    };
+    state.history = content.lines().map(str::to_string).collect();
+    state.history_new = 0;
+    let size = history_size(state);
+    trim_history(&mut state.history, size);
+}
+
+/**
+* append the lines of this session to the history file, which keeps `$HISTSIZE` of them.
+* the file is read again first, so shells exiting one after another all keep their lines
+*/
+fn save_history(state: &ShellState) {
+    let Some(path) = history_file(state) else {
+        return;
+    };
+    // without `histappend` this session's history replaces the file
+    let mut lines: Vec<String> = if state.shopt.histappend {
+        let mut lines: Vec<String> = fs::read_to_string(&path)
+            .map(|content| content.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        lines.extend_from_slice(&state.history[state.history.len() - state.history_new..]);
+        lines
+    } else {
+        state.history.clone()
+    };
+    trim_history(&mut lines, history_size(state));
+    let content: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    if let Err(e) = fs::write(&path, content) {
+        eprintln!("shell: {}: {}", path.display(), e);
     }
+}
 
-    #[test]
-    fn test_words2proc() {
-        let argv = args(&["echo", "a", "b"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["a", "b"]);
-        assert_eq!(result.stdout, None);
-        assert_eq!(result.stderr, None);
+fn history(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let count = match argv.first().map(|arg| arg.parse::<usize>()) {
+        None => state.history.len(),
+        Some(Ok(count)) => count.min(state.history.len()),
+        Some(Err(_)) => {
+            builtin_error(&mut stderr, "history", format_args!("{}: numeric argument required", argv[0]));
+            state.last_status = 1;
+            return state;
+        },
+    };
+    let start = state.history.len() - count;
+    for (index, line) in state.history.iter().enumerate().skip(start) {
+        stdout.write_all(format!("{:>5}  {}\n", index + 1, line).as_bytes()).expect("should success to write");
+    }
+    state
+}
 
-        let argv = args(&["echo", "1", "2"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["1", "2"]);
-        assert_eq!(result.stdout, None);
-        assert_eq!(result.stderr, None);
+/**
+* list the background jobs, reporting the finished ones one last time
+*/
+fn jobs(mut state: ShellState, _argv: &[String], mut stdout: Box<dyn Write>, _: Box<dyn Write>) -> ShellState {
+    let done = reap_jobs(&mut state);
+    let mut lines: Vec<(usize, String)> = state.jobs.iter()
+        .map(|job| (job.number, job_line(job, "Running")))
+        .chain(done.iter().map(|job| (job.number, job_line(job, "Done"))))
+        .collect();
+    lines.sort();
+    for (_, line) in lines {
+        stdout.write_all(format!("{}\n", line).as_bytes()).expect("should success to write");
+    }
+    state
+}
 
-        let argv = args(&["echo", "a", ">", "b"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["a"]);
-        assert_eq!(result.stdout, Some("b"));
-        assert_eq!(result.stdout_mode, RedirMode::Write);
-        assert_eq!(result.stderr, None);
+/**
+* the script `source` reads: a name with a slash is relative to the working directory,
+* others are looked up in `PATH` first, where they need not be executable
+*/
+fn source_path(state: &ShellState, name: &str) -> PathBuf {
+    if !name.contains('/') {
+        let path = variable(state, "PATH").unwrap_or_default();
+        let found = path.split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(name))
+            .find(|path| path.is_file());
+        if let Some(found) = found {
+            return found;
+        }
+    }
+    state.pwd.join(name)
+}
 
-        let argv = args(&["echo", "a", "2", ">", "b"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["a"]);
-        assert_eq!(result.stdout, None);
-        assert_eq!(result.stderr, Some("b"));
-        assert_eq!(result.stderr_mode, RedirMode::Write);
+/**
+* run the commands of the script `file` in this shell, one after another
+*/
+fn eval_script(mut state: ShellState, file: &str, script: &str) -> ShellState {
+    let mut lines = script.split_inclusive('\n').map(str::to_string);
+    let mut consumed = 0;
+    state.sources.push(SourceFrame { file: file.to_string(), line: 0 });
+    while state.exit_code.is_none() {
+        let start = consumed + 1;
+        let next_line = &mut |_| {
+            let line = lines.next()?;
+            consumed += 1;
+            Some(line)
+        };
+        let Some((input, parsed)) = read_command(&state, next_line) else {
+            break;
+        };
+        if let Some(frame) = state.sources.last_mut() {
+            frame.line = start;
+        }
+        match parsed {
+            Ok(argv) => state = eval_line(state, input.trim_end_matches('\n'), &argv),
+            Err(e) => {
+                syntax_error(&state, e);
+                state.last_status = 2;
+            },
+        }
+    }
+    state.sources.pop();
+    state
+}
 
-        let argv = args(&["echo", "a", ">>", "b"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["a"]);
-        assert_eq!(result.stdout, Some("b"));
-        assert_eq!(result.stdout_mode, RedirMode::Append);
-        assert_eq!(result.stderr, None);
+/**
+* report a syntax error, naming the file and line when it is in a script
+*/
+fn syntax_error(state: &ShellState, message: impl fmt::Display) {
+    match state.sources.last() {
+        Some(frame) => builtin_error(&mut io::stderr(), &format!("{}:{}", frame.file, frame.line), message),
+        None => eprintln!("{}", message),
+    }
+}
 
-        let argv = args(&["echo", "a", "2", ">>", "b"]);
-        let result = words2proc(&argv).unwrap();
-        assert_eq!(result.exec, "echo");
-        assert_eq!(result.argv, vec!["a"]);
-        assert_eq!(result.stdout, None);
-        assert_eq!(result.stderr, Some("b"));
-        assert_eq!(result.stderr_mode, RedirMode::Append);
+fn source(mut state: ShellState, argv: &[String], _stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let Some(name) = argv.first() else {
+        builtin_error(&mut stderr, "source", "filename argument required");
+        state.last_status = 2;
+        return state;
+    };
+    let script = match fs::read_to_string(source_path(&state, name)) {
+        Ok(script) => script,
+        Err(e) => {
+            builtin_error(&mut stderr, "source", format_args!("{}: {}", name, io_error_message(&e)));
+            state.last_status = 1;
+            return state;
+        }
+    };
+    eval_script(state, name, &script)
+}
+
+/**
+* start the stopwatch, or print the time since it was started and reset it
+*/
+fn stopwatch_toggle(mut state: ShellState, now: Instant, stdout: &mut dyn Write) -> ShellState {
+    match state.stopwatch.take() {
+        None => state.stopwatch = Some(now),
+        Some(started) => {
+            let elapsed = now.duration_since(started);
+            stdout.write_all(format!("{:.3}s\n", elapsed.as_secs_f64()).as_bytes()).expect("should success to write");
+        }
+    }
+    state
+}
 
+fn stopwatch(state: ShellState, _argv: &[String], mut stdout: Box<dyn Write>, _: Box<dyn Write>) -> ShellState {
+    stopwatch_toggle(state, Instant::now(), &mut stdout)
+}
+
+fn hash(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let path = variable(&state, "PATH").unwrap_or_default();
+    let mut reusable = false;
+    let mut operands = argv.iter();
+    while let Some(arg) = operands.next() {
+        match arg.as_str() {
+            "-r" => state.command_hash.entries.clear(),
+            "-l" => reusable = true,
+            "-p" => {
+                let (Some(file), Some(name)) = (operands.next(), operands.next()) else {
+                    builtin_error(&mut stderr, "hash", "-p: option requires an argument");
+                    state.last_status = 2;
+                    return state;
+                };
+                state.command_hash.sync_path(&path);
+                state.command_hash.entries.insert(name.to_string(), HashEntry { path: PathBuf::from(file), hits: 0 });
+            },
+            name => {
+                if state.command_hash.find(&path, name).is_none() {
+                    builtin_error(&mut stderr, "hash", format_args!("{}: not found", name));
+                    state.last_status = 1;
+                }
+            },
+        }
+    }
+    if !argv.is_empty() && !reusable {
+        return state;
+    }
+
+    let entries = state.command_hash.sorted();
+    if entries.is_empty() {
+        builtin_error(&mut stderr, "hash", "hash table empty");
+        return state;
+    }
+    let mut text = String::new();
+    if reusable {
+        for (name, entry) in entries {
+            text.push_str(&format!("builtin hash -p {} {}\n", entry.path.display(), name));
+        }
+    } else {
+        text.push_str("hits\tcommand\n");
+        for (_, entry) in entries {
+            text.push_str(&format!("{:>4}\t{}\n", entry.hits, entry.path.display()));
+        }
+    }
+    stdout.write_all(text.as_bytes()).expect("should success to write");
+    state
+}
+
+fn help_text(name: &str, builtin: &Builtin, short: bool) -> String {
+    if short {
+        format!("{}: {}\n", name, builtin.synopsis)
+    } else {
+        format!("{}: {}\n    {}\n", name, builtin.synopsis, builtin.description)
+    }
+}
+
+fn help(state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let short = argv.first().is_some_and(|arg| arg == "-s");
+    let patterns = if short { &argv[1..] } else { argv };
+
+    let mut names: Vec<&&str> = BUILTIN_FUNCITONS.keys().collect();
+    names.sort();
+
+    if patterns.is_empty() {
+        for name in names {
+            let text = help_text(name, &BUILTIN_FUNCITONS[*name], short);
+            stdout.write_all(text.as_bytes()).expect("should success to write");
+        }
+        return state;
+    }
+
+    for pattern in patterns {
+        let matched: Vec<&&&str> = names.iter().filter(|name| glob::matches(pattern, name)).collect();
+        if matched.is_empty() {
+            builtin_error(&mut stderr, "help", format_args!("no help topics match `{}'.", pattern));
+            continue;
+        }
+        for name in matched {
+            let text = help_text(name, &BUILTIN_FUNCITONS[**name], short);
+            stdout.write_all(text.as_bytes()).expect("should success to write");
+        }
+    }
+    state
+}
+
+/**
+* quote `value` for a `declare` line
+*/
+fn declare_quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn export(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let operands = match parse_flags(argv, "p", Unknown::Error) {
+        Ok(parsed) => parsed.operands,
+        Err(e) => {
+            builtin_error(&mut stderr, "export", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+
+    if operands.is_empty() {
+        let mut exports: HashMap<String, Option<String>> = env::vars()
+            .filter(|(name, _)| !state.unset.contains(name))
+            .map(|(name, value)| (name, Some(value)))
+            .collect();
+        for name in &state.exported {
+            exports.insert(name.clone(), state.variables.get(name).cloned());
+        }
+        let mut names: Vec<&String> = exports.keys().collect();
+        names.sort();
+        let mut text = String::new();
+        for name in names {
+            match &exports[name] {
+                Some(value) => text.push_str(&format!("declare -x {}={}\n", name, declare_quote(value))),
+                None => text.push_str(&format!("declare -x {}\n", name)),
+            }
+        }
+        stdout.write_all(text.as_bytes()).expect("should success to write");
+        return state;
+    }
+
+    for operand in operands {
+        let (name, value) = match operand.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (operand.as_str(), None),
+        };
+        if !is_name(name) {
+            builtin_error(&mut stderr, "export", format_args!("`{}': not a valid identifier", operand));
+            state.last_status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if state.readonly.contains(name) {
+                builtin_error(&mut stderr, "export", format_args!("{}: readonly variable", name));
+                state.last_status = 1;
+                continue;
+            }
+            state.variables.insert(name.to_string(), value.to_string());
+        }
+        state.exported.insert(name.to_string());
+    }
+    state
+}
+
+fn readonly(mut state: ShellState, argv: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let parsed = match parse_flags(argv, "fp", Unknown::Error) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            builtin_error(&mut stderr, "readonly", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+    let functions = parsed.flags.iter().any(|(flag, _)| *flag == 'f');
+
+    if parsed.operands.is_empty() {
+        if functions {
+            return state;
+        }
+        let mut names: Vec<&String> = state.readonly.iter().collect();
+        names.sort();
+        let mut text = String::new();
+        for name in names {
+            let attributes = if state.exported.contains(name) { "-rx" } else { "-r" };
+            match state.variables.get(name) {
+                Some(value) => text.push_str(&format!("declare {} {}={}\n", attributes, name, declare_quote(value))),
+                None => text.push_str(&format!("declare {} {}\n", attributes, name)),
+            }
+        }
+        stdout.write_all(text.as_bytes()).expect("should success to write");
+        return state;
+    }
+
+    for operand in parsed.operands {
+        let (name, value) = match operand.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (operand.as_str(), None),
+        };
+        // there are no shell functions to mark
+        if functions {
+            builtin_error(&mut stderr, "readonly", format_args!("{}: not a function", name));
+            state.last_status = 1;
+            continue;
+        }
+        if !is_name(name) {
+            builtin_error(&mut stderr, "readonly", format_args!("`{}': not a valid identifier", operand));
+            state.last_status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if state.readonly.contains(name) {
+                builtin_error(&mut stderr, "readonly", format_args!("{}: readonly variable", name));
+                state.last_status = 1;
+                continue;
+            }
+            state.variables.insert(name.to_string(), value.to_string());
+        }
+        state.readonly.insert(name.to_string());
+    }
+    state
+}
+
+/**
+* remove shell variables, and the environment variables they came from.
+* there are no shell functions, so `-f` finds nothing to remove
+*/
+fn unset(mut state: ShellState, argv: &[String], _stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let parsed = match parse_flags(argv, "fv", Unknown::Error) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            builtin_error(&mut stderr, "unset", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+    let functions = parsed.flags.last().is_some_and(|(flag, _)| *flag == 'f');
+    for name in parsed.operands {
+        if !is_name(name) {
+            builtin_error(&mut stderr, "unset", format_args!("`{}': not a valid identifier", name));
+            state.last_status = 1;
+            continue;
+        }
+        if functions {
+            continue;
+        }
+        if state.readonly.contains(name) {
+            builtin_error(&mut stderr, "unset", format_args!("{}: cannot unset: readonly variable", name));
+            state.last_status = 1;
+            continue;
+        }
+        state.variables.remove(name);
+        state.exported.remove(name);
+        state.unset.insert(name.to_string());
+    }
+    state
+}
+
+/**
+* split a line read by `read` into `count` fields on the characters of `ifs`, the last one taking the rest.
+* unless `raw`, a backslash keeps the character after it from splitting and is dropped
+*/
+fn read_fields(line: &str, ifs: &str, count: usize, raw: bool) -> Vec<String> {
+    let mut chars = Vec::new();
+    let mut source = line.chars();
+    while let Some(ch) = source.next() {
+        match ch {
+            '\\' if !raw => chars.extend(source.next().map(|next| (next, true))),
+            ch => chars.push((ch, false)),
+        }
+    }
+    let is_delimiter = |&(ch, escaped): &(char, bool)| !escaped && ifs.contains(ch);
+    let is_space = |c: &(char, bool)| is_delimiter(c) && c.0.is_ascii_whitespace();
+    let collect = |chars: &[(char, bool)]| chars.iter().map(|(ch, _)| ch).collect::<String>();
+
+    let mut rest = &chars[..];
+    while rest.first().is_some_and(is_space) {
+        rest = &rest[1..];
+    }
+    while rest.last().is_some_and(is_space) {
+        rest = &rest[..rest.len() - 1];
+    }
+    let mut fields = Vec::new();
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.iter().position(is_delimiter).unwrap_or(rest.len());
+        fields.push(collect(&rest[..end]));
+        rest = &rest[end..];
+        // whitespace around at most one other character of `ifs` separates two fields
+        while rest.first().is_some_and(is_space) {
+            rest = &rest[1..];
+        }
+        if rest.first().is_some_and(|c| is_delimiter(c) && !is_space(c)) {
+            rest = &rest[1..];
+            while rest.first().is_some_and(is_space) {
+                rest = &rest[1..];
+            }
+        }
+    }
+    fields.push(collect(rest));
+    fields.resize(count, String::new());
+    fields
+}
+
+/**
+* a line of the standard input of a builtin, without reading past it
+*/
+fn read_stdin_line(state: &ShellState) -> io::Result<String> {
+    let Some(file) = &state.stdin else {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line);
+    };
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while (&**file).read(&mut byte)? > 0 {
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn read(mut state: ShellState, argv: &[String], _stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let parsed = match parse_flags(argv, "r", Unknown::Error) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            builtin_error(&mut stderr, "read", e);
+            state.last_status = 2;
+            return state;
+        }
+    };
+    let raw = !parsed.flags.is_empty();
+    let reply = ["REPLY".to_string()];
+    let names = if parsed.operands.is_empty() { &reply[..] } else { parsed.operands };
+    if let Some(name) = names.iter().find(|name| !is_name(name)) {
+        builtin_error(&mut stderr, "read", format_args!("`{}': not a valid identifier", name));
+        state.last_status = 1;
+        return state;
+    }
+
+    let mut line = String::new();
+    loop {
+        let chunk = match read_stdin_line(&state) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                builtin_error(&mut stderr, "read", io_error_message(&e));
+                state.last_status = 1;
+                return state;
+            }
+        };
+        let Some(chunk) = chunk.strip_suffix('\n') else {
+            // end of input, what was read before it is still assigned
+            line.push_str(&chunk);
+            state.last_status = 1;
+            break;
+        };
+        line.push_str(chunk);
+        let trailing = line.chars().rev().take_while(|ch| *ch == '\\').count();
+        if raw || trailing % 2 == 0 {
+            break;
+        }
+        // an unescaped backslash before the newline continues the line
+        line.pop();
+    }
+
+    let ifs = variable(&state, "IFS").unwrap_or(" \t\n".to_string());
+    for (name, value) in names.iter().zip(read_fields(&line, &ifs, names.len(), raw)) {
+        if state.readonly.contains(name) {
+            builtin_error(&mut stderr, "read", format_args!("{}: readonly variable", name));
+            state.last_status = 1;
+            continue;
+        }
+        state.variables.insert(name.clone(), value);
+    }
+    state
+}
+
+fn kill_list(mut state: ShellState, operands: &[String], mut stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    if operands.is_empty() {
+        stdout.write_all(signals::table().as_bytes()).expect("should success to write");
+        return state;
+    }
+    for operand in operands {
+        let line = match operand.parse::<i32>() {
+            // an exit status of a signalled process names its signal
+            Ok(num) => signals::num_to_name(if num > 128 { num - 128 } else { num }).map(str::to_string),
+            Err(_) => signals::name_to_num(operand).map(|num| num.to_string()),
+        };
+        match line {
+            Some(line) => stdout.write_all(format!("{}\n", line).as_bytes()).expect("should success to write"),
+            None => {
+                builtin_error(&mut stderr, "kill", format_args!("{}: invalid signal specification", operand));
+                state.last_status = 1;
+            }
+        }
+    }
+    state
+}
+
+fn kill(mut state: ShellState, argv: &[String], stdout: Box<dyn Write>, mut stderr: Box<dyn Write>) -> ShellState {
+    let (spec, pids) = match argv.first().map(String::as_str) {
+        Some("-l") => return kill_list(state, &argv[1..], stdout, stderr),
+        Some("-s" | "-n") => (argv.get(1).map(String::as_str), argv.get(2..).unwrap_or_default()),
+        Some("--") => (Some("TERM"), &argv[1..]),
+        Some(arg) if arg.len() > 1 && arg.starts_with('-') => (Some(&arg[1..]), &argv[1..]),
+        _ => (Some("TERM"), argv),
+    };
+    if pids.is_empty() {
+        builtin_error(&mut stderr, "kill", "usage: kill [-s sigspec | -n signum | -sigspec] pid ...");
+        state.last_status = 2;
+        return state;
+    }
+    let Some(signal) = spec.and_then(signals::parse) else {
+        builtin_error(&mut stderr, "kill", format_args!("{}: invalid signal specification", spec.unwrap_or_default()));
+        state.last_status = 1;
+        return state;
+    };
+    for pid in pids {
+        let Ok(num) = pid.parse::<u32>() else {
+            builtin_error(&mut stderr, "kill", format_args!("{}: arguments must be process or job IDs", pid));
+            state.last_status = 1;
+            continue;
+        };
+        if let Err(e) = sys::send_signal(num, signal) {
+            builtin_error(&mut stderr, "kill", format_args!("({}) - {}", pid, io_error_message(&e)));
+            state.last_status = 1;
+        }
+    }
+    state
+}
+
+static BUILTIN_FUNCITONS: LazyLock<HashMap<&str, Builtin>> = LazyLock::new(|| -> HashMap<&str, Builtin> {
+    let mut map = HashMap::new();
+    map.insert("echo", Builtin {
+        func: echo,
+        synopsis: "echo [-neE] [arg ...]",
+        description: "Write arguments to the standard output.",
+    });
+    map.insert("exit", Builtin {
+        func: exit,
+        synopsis: "exit [n]",
+        description: "Exit the shell with a status of N.",
+    });
+    map.insert("type", Builtin {
+        func: type_fn,
+        synopsis: "type name",
+        description: "Display information about command type.",
+    });
+    map.insert("which", Builtin {
+        func: which,
+        synopsis: "which name",
+        description: "Locate a command in PATH.",
+    });
+    map.insert("pwd", Builtin {
+        func: pwd,
+        synopsis: "pwd [-LP]",
+        description: "Print the name of the current working directory.",
+    });
+    map.insert("cd", Builtin {
+        func: cd,
+        synopsis: "cd [dir]",
+        description: "Change the shell working directory.",
+    });
+    map.insert("pushd", Builtin {
+        func: pushd,
+        synopsis: "pushd [dir]",
+        description: "Save the current directory on the directory stack and change to dir.",
+    });
+    map.insert("popd", Builtin {
+        func: popd,
+        synopsis: "popd",
+        description: "Remove the top directory from the directory stack and change to it.",
+    });
+    map.insert("dirs", Builtin {
+        func: dirs,
+        synopsis: "dirs",
+        description: "Display the directory stack.",
+    });
+    map.insert("source", Builtin {
+        func: source,
+        synopsis: "source filename",
+        description: "Execute commands from a file in the current shell.",
+    });
+    map.insert(".", Builtin {
+        func: source,
+        synopsis: ". filename",
+        description: "Execute commands from a file in the current shell.",
+    });
+    map.insert("jobs", Builtin {
+        func: jobs,
+        synopsis: "jobs",
+        description: "Display the status of background jobs.",
+    });
+    map.insert("history", Builtin {
+        func: history,
+        synopsis: "history [n]",
+        description: "Display the command history, or its last n entries.",
+    });
+    map.insert("set", Builtin {
+        func: set,
+        synopsis: "set [-n] [+n] [-o option-name] [+o option-name]",
+        description: "Set or unset values of shell options.",
+    });
+    map.insert("shopt", Builtin {
+        func: shopt,
+        synopsis: "shopt [-squ] [optname ...]",
+        description: "Set and unset shell options.",
+    });
+    map.insert("stopwatch", Builtin {
+        func: stopwatch,
+        synopsis: "stopwatch",
+        description: "Start a timer, or print the time since it was started.",
+    });
+    map.insert("hash", Builtin {
+        func: hash,
+        synopsis: "hash [-lr] [-p pathname] [name ...]",
+        description: "Remember or display program locations.",
+    });
+    map.insert("help", Builtin {
+        func: help,
+        synopsis: "help [-s] [pattern ...]",
+        description: "Display information about builtin commands.",
+    });
+    map.insert("export", Builtin {
+        func: export,
+        synopsis: "export [-p] [name[=value] ...]",
+        description: "Set export attribute for shell variables.",
+    });
+    map.insert("readonly", Builtin {
+        func: readonly,
+        synopsis: "readonly [-fp] [name[=value] ...]",
+        description: "Mark shell variables as unchangeable.",
+    });
+    map.insert("read", Builtin {
+        func: read,
+        synopsis: "read [-r] [name ...]",
+        description: "Read a line from the standard input and split it into fields.",
+    });
+    map.insert("unset", Builtin {
+        func: unset,
+        synopsis: "unset [-f] [-v] [name ...]",
+        description: "Remove each variable or function name.",
+    });
+    map.insert("kill", Builtin {
+        func: kill,
+        synopsis: "kill [-s sigspec | -n signum | -sigspec] pid ... or kill -l [sigspec]",
+        description: "Send a signal to a job.",
+    });
+    map
+});
+
+const TIMEOUT_EXIT_CODE: ExitCode = 124;
+const TIMEOUT_KILL_GRACE: Duration = Duration::from_secs(1);
+
+/**
+* timeout for external commands, read from `$SHELL_CMD_TIMEOUT` in seconds
+*/
+fn command_timeout(value: Option<&str>) -> Option<Duration> {
+    let secs = value?.trim().parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(secs).ok().filter(|timeout| !timeout.is_zero())
+}
+
+fn status_code(status: ExitStatus) -> ExitCode {
+    status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/**
+* wait for the child, sending SIGTERM and then SIGKILL once `timeout` has passed.
+* a timed out child yields `TIMEOUT_EXIT_CODE` like timeout(1)
+*/
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<ExitCode> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(status_code);
+    };
+    let Some(deadline) = Instant::now().checked_add(timeout) else {
+        return child.wait().map(status_code);
+    };
+    if let Some(status) = wait_until(child, deadline)? {
+        return Ok(status_code(status));
+    }
+    let _ = sys::send_signal(child.id(), sys::SIGTERM);
+    if wait_until(child, Instant::now() + TIMEOUT_KILL_GRACE)?.is_none() {
+        child.kill()?;
+        child.wait()?;
+    }
+    Ok(TIMEOUT_EXIT_CODE)
+}
+
+fn audit_entry(time: SystemTime, status: ExitCode, line: &str) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("{}\t{}\t{}\n", secs, status, line)
+}
+
+/**
+* append the executed line and its status to `path`.
+* the log is disabled after the first failed write
+*/
+fn write_audit_log(mut state: ShellState, path: Option<&str>, line: &str) -> ShellState {
+    let Some(path) = path.filter(|path| !path.is_empty()) else {
+        return state;
+    };
+    if state.audit_log_disabled {
+        return state;
+    }
+    let entry = audit_entry(SystemTime::now(), state.last_status, line);
+    let result = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(entry.as_bytes()));
+    if let Err(e) = result {
+        eprintln!("shell: {}: {}: audit log disabled", path, e);
+        state.audit_log_disabled = true;
+    }
+    state
+}
+
+/**
+* run a command line the shell read, from the terminal or a script, and record it in the audit log
+*/
+fn eval_line(state: ShellState, line: &str, argv: &[String]) -> ShellState {
+    let state = eval(state, argv);
+    if argv.is_empty() {
+        return state;
+    }
+    let audit_log = variable(&state, "SHELL_AUDIT_LOG");
+    write_audit_log(state, audit_log.as_deref(), line)
+}
+
+#[derive(PartialEq, Debug, Default)]
+struct Invocation {
+    version: bool,
+    debug: bool,
+    noexec: bool,
+    // the file to run instead of reading commands, and the arguments after it
+    script: Option<String>,
+    script_args: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Invocation {
+    let mut invocation = Invocation::default();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--version" => invocation.version = true,
+            "--debug" => invocation.debug = true,
+            "-n" => invocation.noexec = true,
+            arg if !arg.starts_with('-') => {
+                invocation.script = Some(arg.to_string());
+                invocation.script_args = rest.cloned().collect();
+                break;
+            },
+            _ => {}
+        }
+    }
+    invocation
+}
+
+fn version_string() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let invocation = parse_args(&args);
+    if invocation.version {
+        println!("{}", version_string());
+        return;
+    }
+    let mut state = ShellState::default();
+    // a shell variable only, commands do not inherit it
+    state.variables.insert("SHELL_VERSION".to_string(), env!("CARGO_PKG_VERSION").to_string());
+    state.interactive = invocation.script.is_none() && io::stdin().is_terminal() && io::stdout().is_terminal();
+    state.debug = invocation.debug;
+    state.options.noexec = invocation.noexec;
+    state.options.monitor = state.interactive;
+    state.args = invocation.script.iter().chain(&invocation.script_args).cloned().collect();
+    if state.args.is_empty() {
+        state.args.extend(args.first().cloned());
+    }
+    sys::catch_interrupt();
+    if let Some(path) = &invocation.script {
+        let script = match fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("shell: {}: {}", path, io_error_message(&e));
+                shutdown(state, 127);
+            }
+        };
+        let state = eval_script(state, path, &script);
+        let code = state.exit_code.unwrap_or(state.last_status);
+        shutdown(state, code);
+    }
+    if state.interactive {
+        load_history(&mut state);
+    }
+
+    // Wait for user input
+    while state.exit_code.is_none() {
+        for job in reap_jobs(&mut state) {
+            if state.options.monitor {
+                eprintln!("{}", job_line(&job, "Done"));
+            }
+        }
+        let mut next_line = |more: bool| if more {
+            read_input(&state, &render_prompt(&state, "PS2", "> "))
+        } else {
+            read_input(&state, &render_prompt(&state, "PS1", "$ "))
+        };
+        let Some((input, parsed)) = read_command(&state, &mut next_line) else {
+            state.exit_code = Some(state.last_status);
+            break;
+        };
+        let line = input.trim_end_matches('\n');
+        if !line.trim().is_empty() {
+            add_history(&mut state, line);
+        }
+        match parsed {
+            Ok(argv) => state = eval_line(state, line, &argv),
+            Err(e) => syntax_error(&state, e),
+        }
+    }
+    let code = state.exit_code.unwrap_or(state.last_status);
+    shutdown(state, code);
+}
+
+/**
+* replace the backslash escapes of a prompt, keeping what they give literal.
+* unknown escapes are left as they are
+*/
+fn decode_prompt(state: &ShellState, ps1: &str) -> String {
+    let mut result = String::new();
+    let mut chars = ps1.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        let text = match chars.next() {
+            Some('w') => abbreviate_home(state, &state.pwd),
+            Some('u') => variable(state, "USER").or_else(sys::user_name).unwrap_or_default(),
+            Some('h') => sys::hostname().unwrap_or_default().split('.').next().unwrap_or_default().to_string(),
+            Some('$') => if sys::is_root() { "#" } else { "$" }.to_string(),
+            Some('n') => "\n".to_string(),
+            Some('\\') => "\\".to_string(),
+            Some(other) => format!("\\{}", other),
+            None => "\\".to_string(),
+        };
+        // nothing in a directory or host name is expanded afterwards
+        for ch in text.chars() {
+            if matches!(ch, '"' | '\'' | '\\' | '$' | '`' | '~') {
+                result.push('\\');
+            }
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/**
+* whether a prompt has nothing to expand, only backslash escapes like `\$`
+*/
+fn prompt_is_static(ps1: &str) -> bool {
+    let mut chars = ps1.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            },
+            '$' | '`' => return false,
+            _ => {},
+        }
+    }
+    true
+}
+
+/**
+* the prompt in variable `name` with its escapes decoded, then expanded and unquoted,
+* so `$?` shows the status of the last command. rendering it leaves the state alone.
+* a prompt with nothing to expand only depends on its escapes, so it is kept until
+* it, the working directory, `HOME` or `USER` change
+*/
+fn render_prompt(state: &ShellState, name: &str, default: &str) -> String {
+    let Some(ps1) = variable(state, name) else {
+        return default.to_string();
+    };
+    let cacheable = prompt_is_static(&ps1);
+    let inputs = (ps1, state.pwd.clone(), variable(state, "HOME"), variable(state, "USER"));
+    if let Some(cached) = state.prompt_cache.borrow().get(name).filter(|cached| cacheable && cached.inputs == inputs) {
+        return cached.rendered.clone();
+    }
+
+    let decoded = decode_prompt(state, &inputs.0);
+    let rendered = match expand(&decoded, &Expansion(state)) {
+        Ok(expanded) => unescape(&expanded),
+        Err(_) => unescape(&decoded),
+    };
+    let mut cache = state.prompt_cache.borrow_mut();
+    if cacheable {
+        cache.insert(name.to_string(), CachedPrompt { inputs, rendered: rendered.clone() });
+    } else {
+        cache.remove(name);
+    }
+    rendered
+}
+
+/**
+* read a command from the lines `next_line` gives, with the tokens it parses to.
+* a trailing backslash or an unclosed quote goes on over the next lines,
+* and `next_line` is told when the line it gives continues the command
+*/
+fn read_command(state: &ShellState, next_line: &mut dyn FnMut(bool) -> Option<String>) -> Option<(String, Result<Vec<String>, ParseError>)> {
+    let mut input = next_line(false)?;
+    let parsed = loop {
+        if continues_line(&input) {
+            input.truncate(input.len() - 2);
+            if let Some(more) = next_line(true) {
+                input.push_str(&more);
+                continue;
+            }
+        }
+        match parse(state, &input) {
+            Err(ParseError::QuoteMissing) => match next_line(true) {
+                Some(more) => input.push_str(&more),
+                None => break Err(ParseError::QuoteMissing),
+            },
+            parsed => break parsed,
+        }
+    };
+    Some((input, parsed))
+}
+
+/**
+* the next line of input, with tab completion on a terminal
+*/
+fn read_input(state: &ShellState, prompt: &str) -> Option<String> {
+    if state.interactive {
+        let candidates = |word: &str, first: bool| if first {
+            command_candidates(state, word)
+        } else {
+            editor::path_candidates(word, &state.pwd)
+        };
+        match editor::read_line(prompt, &candidates) {
+            Ok(line) => return line,
+            // without control over the terminal, let it edit the line
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                print!("{}", prompt);
+                let _ = io::stdout().flush();
+            },
+            // a terminal that can no longer be read from is the end of input
+            Err(_) => return None,
+        }
+    }
+    let mut input = Vec::new();
+    match io::stdin().lock().read_until(b'\n', &mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(String::from_utf8_lossy(&input).into_owned()),
+    }
+}
+
+/**
+* leave the shell with `code`.
+* the `exit` builtin and end of input both end up here, so whatever has to happen
+* before the shell goes away runs in a single place and order
+*/
+fn shutdown(state: ShellState, code: ExitCode) -> ! {
+    if state.interactive {
+        save_history(&state);
+    }
+    let _ = io::stdout().flush();
+    drop(state);
+    std::process::exit(code)
+}
+
+/**
+* where a pipeline stage reads its standard input from
+*/
+enum StageInput {
+    Inherit,
+    Pipe(File),
+    Buffer(Vec<u8>),
+}
+
+/**
+* the read end of a pipe giving back `data`.
+* another thread writes it, so a buffer larger than the pipe cannot block the shell
+*/
+fn buffer_reader(data: Vec<u8>) -> io::Result<File> {
+    let (read, mut write) = sys::pipe()?;
+    thread::spawn(move || {
+        let _ = write.write_all(&data);
+    });
+    Ok(read)
+}
+
+/**
+* in-memory writer which can still be read after being boxed
+*/
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Capture {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+fn open_redirect(pwd: &Path, target: &str, mode: &RedirMode) -> io::Result<File> {
+    let filename = pwd.join(target);
+    match mode {
+        RedirMode::Read => File::open(filename),
+        RedirMode::Write => File::create(filename),
+        RedirMode::Append => File::options()
+            .create(true)
+            .append(true)
+            .open(filename),
+    }
+}
+
+/**
+* where a file descriptor of a stage points after its redirections.
+* `Default(n)` is what descriptor `n` would be without any redirection
+*/
+#[derive(Clone)]
+enum Stream {
+    Default(usize),
+    File(Rc<File>),
+}
+
+/**
+* describe an io error the way shells do, without the os error number
+*/
+fn io_error_message(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        io::ErrorKind::IsADirectory => "Is a directory".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/**
+* apply the redirections of `proc` in order to stdin, stdout and stderr,
+* reporting the first one that fails as `target: message`
+*/
+fn open_redirects(pwd: &Path, proc: &Proc) -> Result<[Stream; 3], String> {
+    let mut streams = [Stream::Default(0), Stream::Default(1), Stream::Default(2)];
+    for redirect in &proc.redirects {
+        if redirect.fd >= streams.len() {
+            return Err(format!("{}: Bad file descriptor", redirect.fd));
+        }
+        streams[redirect.fd] = match &redirect.target {
+            RedirTarget::File(target, mode) => {
+                let f = open_redirect(pwd, target, mode).map_err(|e| format!("{}: {}", target, io_error_message(&e)))?;
+                Stream::File(Rc::new(f))
+            },
+            RedirTarget::Fd(to) => streams.get(*to).cloned().ok_or(format!("{}: Bad file descriptor", to))?,
+        };
+    }
+    Ok(streams)
+}
+
+/**
+* the `Stdio` for an output stream of an external command, `None` to inherit it.
+* `stdout` replaces the shell's standard output, for a pipe or a substitution
+*/
+fn output_stdio(stream: &Stream, fd: usize, stdout: Option<&File>) -> io::Result<Option<Stdio>> {
+    match stream {
+        Stream::File(f) => Ok(Some(f.try_clone()?.into())),
+        Stream::Default(1) => match stdout {
+            Some(out) => Ok(Some(out.try_clone()?.into())),
+            None if fd == 1 => Ok(None),
+            None => Ok(Some(io::stdout().as_fd().try_clone_to_owned()?.into())),
+        },
+        Stream::Default(2) if fd != 2 => Ok(Some(io::stderr().as_fd().try_clone_to_owned()?.into())),
+        Stream::Default(_) => Ok(None),
+    }
+}
+
+/**
+* the writer a builtin gets for an output stream.
+* the shell's standard output is `capture` for the next stage unless this is the last one
+*/
+fn builtin_writer(state: &ShellState, stream: &Stream, is_last: bool, capture: &Capture) -> io::Result<Box<dyn Write>> {
+    match stream {
+        Stream::File(f) => Ok(Box::new(f.try_clone()?)),
+        Stream::Default(2) => Ok(Box::new(io::stderr())),
+        Stream::Default(_) if is_last => match &state.stdout {
+            Some(out) => Ok(Box::new(out.try_clone()?)),
+            None => Ok(Box::new(io::stdout())),
+        },
+        Stream::Default(_) => Ok(Box::new(capture.clone())),
+    }
+}
+
+/**
+* start one stage of a pipeline.
+* builtins run to completion and hand their output to the next stage as a buffer,
+* external commands are spawned and returned to be waited for
+*/
+fn run_stage(mut state: ShellState, proc: Proc, input: StageInput, is_last: bool) -> (ShellState, StageInput, Option<Child>) {
+    let mut words = vec![proc.exec.as_str()];
+    words.extend(proc.argv.iter().map(|arg| arg.as_str()));
+    trace(&state, "argv", &words);
+
+    if let Some((name, _)) = proc.assignments.iter().find(|(name, _)| state.readonly.contains(name)) {
+        eprintln!("shell: {}: readonly variable", name);
+        state.last_status = 1;
+        return (state, StageInput::Buffer(Vec::new()), None);
+    }
+
+    let [stdin, stdout, stderr] = match open_redirects(&state.pwd, &proc) {
+        Ok(streams) => streams,
+        Err(message) => {
+            eprintln!("shell: {}", message);
+            state.last_status = 1;
+            return (state, StageInput::Buffer(Vec::new()), None);
+        }
+    };
+
+    if proc.exec.is_empty() {
+        state.variables.extend(proc.assignments);
+        state.last_status = 0;
+        return (state, StageInput::Buffer(Vec::new()), None);
+    }
+
+    if let Some(builtin) = BUILTIN_FUNCITONS.get(proc.exec.as_str()) {
+        let capture = Capture::default();
+        let writers = builtin_writer(&state, &stdout, is_last, &capture).and_then(|out| {
+            Ok((out, builtin_writer(&state, &stderr, is_last, &capture)?))
+        });
+        let (writer, err_writer) = match writers {
+            Ok(writers) => writers,
+            Err(e) => {
+                eprintln!("shell: {}", io_error_message(&e));
+                state.last_status = 1;
+                return (state, StageInput::Buffer(Vec::new()), None);
+            }
+        };
+        state.previous_status = state.last_status;
+        state.last_status = 0;
+        let input = match (stdin, input) {
+            (Stream::File(f), _) => Some(f),
+            (_, StageInput::Inherit) => state.stdin.clone(),
+            (_, StageInput::Pipe(out)) => Some(Rc::new(out)),
+            (_, StageInput::Buffer(data)) => match buffer_reader(data) {
+                Ok(read) => Some(Rc::new(read)),
+                Err(e) => {
+                    eprintln!("shell: {}", io_error_message(&e));
+                    state.last_status = 1;
+                    return (state, StageInput::Buffer(Vec::new()), None);
+                }
+            },
+        };
+        let outer = std::mem::replace(&mut state.stdin, input);
+        let mut state = (builtin.func)(state, &proc.argv, writer, err_writer);
+        state.stdin = outer;
+        (state, StageInput::Buffer(capture.take()), None)
+    } else if let Some(exe) = state.command_hash.lookup(&variable(&state, "PATH").unwrap_or_default(), &proc.exec) {
+        let mut cmd = Command::new(exe);
+        for name in &state.unset {
+            cmd.env_remove(name);
+        }
+        cmd.arg0(&proc.exec)
+            .args(&proc.argv)
+            .envs(exported_variables(&state))
+            .envs(proc.assignments.iter().map(|(name, value)| (name, value)))
+            .current_dir(state.pwd.clone());
+        let buffer = match (stdin, input) {
+            (Stream::File(f), _) => {
+                match f.try_clone() {
+                    Ok(f) => {
+                        cmd.stdin(f);
+                    },
+                    Err(e) => eprintln!("shell: {}", io_error_message(&e)),
+                }
+                None
+            },
+            (_, StageInput::Inherit) => None,
+            (_, StageInput::Pipe(out)) => {
+                cmd.stdin(out);
+                None
+            },
+            (_, StageInput::Buffer(data)) => {
+                cmd.stdin(Stdio::piped());
+                Some(data)
+            },
+        };
+
+        let (next, pipe) = if is_last {
+            (StageInput::Buffer(Vec::new()), None)
+        } else {
+            match sys::pipe() {
+                Ok((read, write)) => (StageInput::Pipe(read), Some(write)),
+                Err(e) => {
+                    eprintln!("shell: {}", io_error_message(&e));
+                    state.last_status = 1;
+                    return (state, StageInput::Buffer(Vec::new()), None);
+                }
+            }
+        };
+        let target = pipe.as_ref().or(state.stdout.as_deref());
+        let outputs = output_stdio(&stdout, 1, target).and_then(|out| {
+            Ok((out, output_stdio(&stderr, 2, target)?))
+        });
+        match outputs {
+            Ok((out, err)) => {
+                if let Some(out) = out {
+                    cmd.stdout(out);
+                }
+                if let Some(err) = err {
+                    cmd.stderr(err);
+                }
+            },
+            Err(e) => {
+                eprintln!("shell: {}", io_error_message(&e));
+                state.last_status = 1;
+                return (state, StageInput::Buffer(Vec::new()), None);
+            }
+        }
+
+        let spawned = cmd.spawn();
+        // our copies of the write end must be closed for the reader to see EOF
+        drop(cmd);
+        drop(pipe);
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("{}: {}", proc.exec, e);
+                state.last_status = 126;
+                return (state, StageInput::Buffer(Vec::new()), None);
+            }
+        };
+        if let (Some(data), Some(mut stdin)) = (buffer, child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&data);
+            });
+        }
+        (state, next, Some(child))
+    } else if state.shopt.autocd && state.pwd.join(&proc.exec).is_dir() {
+        let capture = Capture::default();
+        let out = builtin_writer(&state, &stdout, is_last, &capture).unwrap_or_else(|_| Box::new(io::stdout()));
+        let err = builtin_writer(&state, &stderr, is_last, &capture).unwrap_or_else(|_| Box::new(io::stderr()));
+        let state = cd(state, &[proc.exec.to_string()], out, err);
+        (state, StageInput::Buffer(capture.take()), None)
+    } else {
+        let capture = Capture::default();
+        let mut err = builtin_writer(&state, &stderr, is_last, &capture).unwrap_or_else(|_| Box::new(io::stderr()));
+        let _ = writeln!(err, "{}: command not found", proc.exec);
+        drop(err);
+        state.last_status = 127;
+        (state, StageInput::Buffer(capture.take()), None)
+    }
+}
+
+/**
+* run a stage the way a subshell would, so only its status gets back to the shell
+*/
+fn run_subshell_stage(mut state: ShellState, proc: Proc, input: StageInput, is_last: bool) -> (ShellState, StageInput, Option<Child>) {
+    let (stage_state, next, child) = run_stage(state.clone(), proc, input, is_last);
+    state.last_status = stage_state.exit_code.unwrap_or(stage_state.last_status);
+    (state, next, child)
+}
+
+/**
+* run the stages connected by pipes; the status is the one of the last stage
+*/
+fn run_pipeline(mut state: ShellState, pipeline: Vec<Proc>) -> ShellState {
+    let last = pipeline.len() - 1;
+    let mut input = StageInput::Inherit;
+    let mut children = Vec::<(Child, bool)>::new();
+    // a builtin alone runs in the shell, one in a longer pipeline may not change it
+    let run = if last == 0 { run_stage } else { run_subshell_stage };
+    for (index, proc) in pipeline.into_iter().enumerate() {
+        let (next_state, next_input, child) = run(state, proc, input, index == last);
+        state = next_state;
+        input = next_input;
+        if let Some(child) = child {
+            children.push((child, index == last));
+        }
+    }
+
+    let timeout = command_timeout(variable(&state, "SHELL_CMD_TIMEOUT").as_deref());
+    for (mut child, is_last) in children {
+        let status = wait_with_timeout(&mut child, timeout).unwrap_or(1);
+        if is_last {
+            state.last_status = status;
+        }
+    }
+    // the terminal echoed a ^C that interrupted the command, end its line
+    if state.interactive && state.last_status == 128 + sys::SIGINT {
+        println!();
+    }
+    state
+}
+
+/**
+* start a pipeline without waiting for it, reading from nothing instead of the terminal.
+* only monitor mode announces it; a script just goes on
+*/
+fn run_background(mut state: ShellState, pipeline: Vec<Proc>, command: String) -> ShellState {
+    let last = pipeline.len() - 1;
+    let mut input = StageInput::Buffer(Vec::new());
+    let mut pids = Vec::new();
+    for (index, proc) in pipeline.into_iter().enumerate() {
+        let (next_state, next_input, child) = run_subshell_stage(state, proc, input, index == last);
+        state = next_state;
+        input = next_input;
+        pids.extend(child.map(|child| child.id()));
+    }
+    state.last_status = 0;
+    let Some(&pid) = pids.last() else {
+        return state;
+    };
+    let number = state.jobs.iter().map(|job| job.number).max().unwrap_or(0) + 1;
+    if state.options.monitor {
+        eprintln!("[{}] {}", number, pid);
+    }
+    state.jobs.push(Job { number, pids, command });
+    state
+}
+
+/**
+* take the jobs whose processes have all exited out of the job list
+*/
+fn reap_jobs(state: &mut ShellState) -> Vec<Job> {
+    for job in &mut state.jobs {
+        job.pids.retain(|pid| !sys::try_reap(*pid));
+    }
+    let (done, running) = std::mem::take(&mut state.jobs).into_iter().partition(|job| job.pids.is_empty());
+    state.jobs = running;
+    done
+}
+
+fn job_line(job: &Job, status: &str) -> String {
+    format!("[{}]  {}\t{}", job.number, status, job.command)
+}
+
+/**
+* the value of the shell parameter `name`
+*/
+fn variable(state: &ShellState, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(state.last_status.to_string()),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => name.parse::<usize>().ok().and_then(|n| state.args.get(n).cloned()),
+        _ => match state.variables.get(name) {
+            Some(value) => Some(value.clone()),
+            None if state.unset.contains(name) => None,
+            None => env::var(name).ok(),
+        },
+    }
+}
+
+/**
+* run `src` in a copy of the shell and collect what it writes to standard output
+*/
+fn command_output(mut state: ShellState, src: &str) -> Vec<u8> {
+    let tokens = match parse(&state, src) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            return Vec::new();
+        }
+    };
+    let (mut read, write) = match sys::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            eprintln!("shell: {}", io_error_message(&e));
+            return Vec::new();
+        }
+    };
+    // read while the command runs so a full pipe cannot block it
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = io::Read::read_to_end(&mut read, &mut output);
+        output
+    });
+    state.stdout = Some(Rc::new(write));
+    drop(eval(state, &tokens));
+    reader.join().unwrap_or_default()
+}
+
+/**
+* expansion in the context of a shell state.
+* substitutions run in a copy, so their side effects do not reach the shell
+*/
+struct Expansion<'a>(&'a ShellState);
+impl Context for Expansion<'_> {
+    fn variable(&self, name: &str) -> Option<String> {
+        variable(self.0, name)
+    }
+    fn substitute(&self, command: &str) -> String {
+        let output = command_output(self.0.clone(), command);
+        String::from_utf8_lossy(&output).trim_end_matches('\n').to_string()
+    }
+    fn home_dir(&self, user: &str) -> Option<String> {
+        if user.is_empty() {
+            variable(self.0, "HOME")
+        } else {
+            sys::home_dir(user)
+        }
+    }
+}
+
+/**
+* the shell variables passed to child processes on top of the inherited environment
+*/
+fn exported_variables(state: &ShellState) -> Vec<(&str, &str)> {
+    state.exported.iter()
+        .filter_map(|name| Some((name.as_str(), state.variables.get(name)?.as_str())))
+        .collect()
+}
+
+/**
+* how a command of a list depends on the status of the one before it
+*/
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Connector {
+    Always,
+    And,
+    Or,
+}
+
+/**
+* a pipeline of a list, with the connector before it and whether it runs in the background
+*/
+type ListItem<'a> = (Connector, &'a [String], bool);
+
+/**
+* split the tokens on `;`, `&`, `&&` and `||` into pipelines.
+* empty commands around `;` are skipped, and the pipelines are checked here
+* so nothing runs when the line is malformed
+*/
+fn words2list(tokens: &[String]) -> Result<Vec<ListItem<'_>>, &'static str> {
+    let mut list = Vec::new();
+    let mut connector = Connector::Always;
+    let mut start = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        let (next, name) = match token.as_str() {
+            ";" => (Connector::Always, ";"),
+            "&" => (Connector::Always, "&"),
+            "&&" => (Connector::And, "&&"),
+            "||" => (Connector::Or, "||"),
+            _ => continue,
+        };
+        if index == start {
+            if connector != Connector::Always || next != Connector::Always || name == "&" {
+                return Err(name);
+            }
+        } else {
+            list.push((connector, &tokens[start..index], name == "&"));
+            connector = next;
+        }
+        start = index + 1;
+    }
+    if start < tokens.len() {
+        list.push((connector, &tokens[start..], false));
+    } else if connector != Connector::Always {
+        return Err("newline");
+    }
+    for (_, pipeline, _) in &list {
+        words2pipeline(pipeline)?;
+        for stage in pipeline.split(|token| token == "|") {
+            if let Some(word) = CLOSING_RESERVED_WORDS.iter().find(|word| **word == stage[0]) {
+                return Err(word);
+            }
+        }
+    }
+    Ok(list)
+}
+
+/**
+* reserved words which only continue or close a compound command.
+* there are no compound commands yet, so one of these starting a command is always misplaced
+*/
+const CLOSING_RESERVED_WORDS: [&str; 9] = ["then", "else", "elif", "fi", "do", "done", "esac", "in", "}"];
+
+/**
+* the paths a word with unquoted wildcards matches.
+* a word matching nothing stays as it is, or is dropped with `nullglob`
+*/
+fn glob_word(state: &ShellState, word: String) -> Vec<String> {
+    let Some(pattern) = glob::pattern(&word) else {
+        return vec![word];
+    };
+    let paths = glob::expand(&state.pwd, &pattern, state.shopt.dotglob);
+    if paths.is_empty() && !state.shopt.nullglob {
+        return vec![word];
+    }
+    paths.iter().map(|path| literal(path, false)).collect()
+}
+
+/**
+* expand a raw token into the words it stands for, in the order the shell defines:
+* tilde, then parameters, commands and arithmetic from left to right,
+* then word splitting and filenames.
+* the results are still raw, quote removal is left to `words2proc`.
+* a quoted `"$x"` is neither split nor globbed, and an `assigning` token is never.
+* there is no brace expansion yet
+*/
+fn expand_word(state: &ShellState, token: &str, assigning: bool) -> Result<Vec<String>, String> {
+    let word = expand(token, &Expansion(state))?;
+    if assigning {
+        return Ok(vec![word]);
+    }
+    Ok(split_fields(&word).into_iter().flat_map(|field| glob_word(state, field)).collect())
+}
+
+fn expand_words(state: &ShellState, tokens: &[String]) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut assigning = true;
+    for token in tokens {
+        // assignments before the command are never globbed
+        assigning &= assignment(token).is_some();
+        words.extend(expand_word(state, token, assigning)?);
+    }
+    Ok(words)
+}
+
+/**
+* expand and run one pipeline. a `background` one is not waited for,
+* though builtins in it still run to completion first
+*/
+fn eval_pipeline(mut state: ShellState, tokens: &[String], background: bool) -> ShellState {
+    let tokens = match expand_words(&state, tokens) {
+        Ok(tokens) => tokens,
+        Err(message) => {
+            eprintln!("shell: {}", message);
+            state.last_status = 1;
+            return state;
+        }
+    };
+    let pipeline = match words2pipeline(&tokens) {
+        Ok(pipeline) => pipeline,
+        Err(token) => {
+            syntax_error(&state, format_args!("syntax error near unexpected token `{}'", token));
+            state.last_status = 2;
+            return state;
+        }
+    };
+    if background {
+        return run_background(state, pipeline, tokens.join(" "));
+    }
+    run_pipeline(state, pipeline)
+}
+
+fn eval(mut state: ShellState, tokens: &[String]) -> ShellState {
+    if tokens.is_empty() {
+        return state;
+    }
+    let list = match words2list(tokens) {
+        Ok(list) => list,
+        Err(token) => {
+            syntax_error(&state, format_args!("syntax error near unexpected token `{}'", token));
+            state.last_status = 2;
+            return state;
+        }
+    };
+    // only check the commands; an interactive shell could never turn this off again
+    if state.options.noexec && !state.interactive {
+        return state;
+    }
+    for (connector, pipeline, background) in list {
+        let run = match connector {
+            Connector::Always => true,
+            Connector::And => state.last_status == 0,
+            Connector::Or => state.last_status != 0,
+        };
+        if run {
+            state = eval_pipeline(state, pipeline, background);
+        }
+        if state.exit_code.is_some() {
+            break;
+        }
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn run_builtin(f: BuiltinFunction, argv: &[&str]) -> String {
+        run_builtin_with(ShellState::default(), f, argv).1
+    }
+
+    fn run_builtin_with(state: ShellState, f: BuiltinFunction, argv: &[&str]) -> (ShellState, String) {
+        let out = Capture::default();
+        let state = f(state, &args(argv), Box::new(out.clone()), Box::new(io::stderr()));
+        (state, String::from_utf8(out.take()).unwrap())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&args(&["shell"])), Invocation::default());
+        assert!(parse_args(&args(&["shell", "--version"])).version);
+        let invocation = parse_args(&args(&["shell", "--debug"]));
+        assert!(invocation.debug);
+        assert!(!invocation.version);
+        let invocation = parse_args(&args(&["shell", "-n", "run.sh", "a", "--debug"]));
+        assert_eq!((invocation.noexec, invocation.debug), (true, false));
+        assert_eq!(invocation.script.as_deref(), Some("run.sh"));
+        assert_eq!(invocation.script_args, args(&["a", "--debug"]));
+        assert_eq!(version_string(), format!("codecrafters-shell {}", env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        assert_eq!(command_timeout(None), None);
+        assert_eq!(command_timeout(Some("")), None);
+        assert_eq!(command_timeout(Some("0")), None);
+        assert_eq!(command_timeout(Some("abc")), None);
+        assert_eq!(command_timeout(Some("-1")), None);
+        assert_eq!(command_timeout(Some("2")), Some(Duration::from_secs(2)));
+        assert_eq!(command_timeout(Some("0.5")), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_wait_with_timeout() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let started = Instant::now();
+        let code = wait_with_timeout(&mut child, Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(code, TIMEOUT_EXIT_CODE);
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        assert_eq!(wait_with_timeout(&mut child, Some(Duration::from_secs(5))).unwrap(), 3);
+
+        let mut child = Command::new("sh").args(["-c", "exit 4"]).spawn().unwrap();
+        assert_eq!(wait_with_timeout(&mut child, None).unwrap(), 4);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shell-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_which_internal_requires_execute_permission() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("which");
+        let script = dir.join("script");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        let path = dir.to_str().unwrap();
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(which_internal(path, "script"), None);
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(which_internal(path, "script"), Some(script));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let path = env::var("PATH").unwrap_or_default();
+        assert_eq!(resolve(&path, "cd"), Resolution::Builtin);
+        assert_eq!(resolve(&path, "type"), Resolution::Builtin);
+        let Resolution::File(path) = resolve(&path, "ls") else {
+            panic!("ls should be found in PATH");
+        };
+        assert!(path.ends_with("ls"));
+        assert_eq!(resolve("/usr/bin:/bin", "nosuchcommand_for_test"), Resolution::NotFound);
+    }
+
+    #[test]
+    fn test_command_hash() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("hash");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        for d in [&first, &second] {
+            fs::write(d.join("cmd"), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(d.join("cmd"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path = format!("{}:{}", first.display(), second.display());
+
+        let mut hash = CommandHash::default();
+        assert_eq!(hash.lookup(&path, "cmd"), Some(first.join("cmd")));
+        assert_eq!(hash.entries.len(), 1);
+        assert_eq!(hash.lookup(&path, "nothing"), None);
+        assert_eq!(hash.entries.len(), 1);
+
+        fs::remove_file(first.join("cmd")).unwrap();
+        assert_eq!(hash.lookup(&path, "cmd"), Some(second.join("cmd")));
+
+        let path = first.display().to_string();
+        assert_eq!(hash.lookup(&path, "cmd"), None);
+        assert!(hash.entries.is_empty());
+    }
+
+    #[test]
+    fn test_hash_hits() {
+        let mut state = ShellState::default();
+        state = eval(state, &args(&["true"]));
+        state = eval(state, &args(&["true"]));
+        assert_eq!(state.command_hash.entries["true"].hits, 2);
+        let true_path = state.command_hash.entries["true"].path.clone();
+
+        let (state, out) = run_builtin_with(state, hash, &[]);
+        assert_eq!(out, format!("hits\tcommand\n   2\t{}\n", true_path.display()));
+
+        let (state, out) = run_builtin_with(state, hash, &["-l"]);
+        assert_eq!(out, format!("builtin hash -p {} true\n", true_path.display()));
+
+        let (state, out) = run_builtin_with(state, hash, &["-r"]);
+        assert_eq!(out, "");
+        assert!(state.command_hash.entries.is_empty());
+
+        let (state, _) = run_builtin_with(state, hash, &["-p", "/bin/sh", "mysh"]);
+        assert_eq!(state.command_hash.entries["mysh"].path, PathBuf::from("/bin/sh"));
+        assert_eq!(state.command_hash.entries["mysh"].hits, 0);
+    }
+
+    #[test]
+    fn test_audit_entry() {
+        let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(audit_entry(time, 1, "ls -a"), "1700000000\t1\tls -a\n");
+    }
+
+    #[test]
+    fn test_write_audit_log() {
+        let dir = temp_dir("audit");
+        let log = dir.join("audit.log");
+        let log = log.to_str().unwrap();
+
+        let mut state = ShellState::default();
+        state.last_status = 127;
+        let state = write_audit_log(state, Some(log), "nosuchcmd");
+        let state = write_audit_log(state, Some(log), "echo hi");
+        let content = fs::read_to_string(log).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("\t127\tnosuchcmd"));
+        assert!(lines[1].ends_with("\t127\techo hi"));
+        assert!(!state.audit_log_disabled);
+
+        let missing = dir.join("missing").join("audit.log");
+        let state = write_audit_log(state, missing.to_str(), "echo hi");
+        assert!(state.audit_log_disabled);
+        let state = write_audit_log(state, Some(log), "echo again");
+        assert!(state.audit_log_disabled);
+        assert_eq!(fs::read_to_string(log).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_shopt() {
+        let (state, out) = run_builtin_with(ShellState::default(), shopt, &["-s", "nullglob", "autocd"]);
+        assert_eq!(out, "");
+        assert!(state.shopt.nullglob);
+        assert!(state.shopt.autocd);
+
+        let (state, out) = run_builtin_with(state, shopt, &["-s"]);
+        assert_eq!(out, "autocd         \ton\nnullglob       \ton\n");
+
+        let (state, out) = run_builtin_with(state, shopt, &["-u", "autocd"]);
+        assert_eq!(out, "");
+        assert!(!state.shopt.autocd);
+
+        let (state, out) = run_builtin_with(state, shopt, &["-q", "nullglob"]);
+        assert_eq!((state.last_status, out.as_str()), (0, ""));
+        let (state, _) = run_builtin_with(state, shopt, &["-q", "nullglob", "autocd"]);
+        assert_eq!(state.last_status, 1);
+
+        let (state, out) = run_builtin_with(state, shopt, &["dotglob"]);
+        assert_eq!((state.last_status, out.as_str()), (1, "dotglob        \toff\n"));
+        let (state, _) = run_builtin_with(state, shopt, &["-q", "extglob"]);
+        assert_eq!(state.last_status, 1);
+        let (mut state, _) = run_builtin_with(state, shopt, &["-s", "checkwinsize", "cdspell", "extglob"]);
+        assert!(state.shopt.checkwinsize && state.shopt.cdspell && state.shopt.extglob);
+        state.last_status = 0;
+        let (state, _) = run_builtin_with(state, shopt, &["-q", "checkwinsize", "extglob"]);
+        assert_eq!(state.last_status, 0);
+        let (state, _) = run_builtin_with(state, shopt, &["-q", "nosuchoption"]);
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_echo() {
+        assert_eq!(run_builtin(echo, &["a", "b"]), "a b\n");
+        assert_eq!(run_builtin(echo, &[]), "\n");
+    }
+
+    #[test]
+    fn test_echo_options() {
+        assert_eq!(run_builtin(echo, &["-n", "hi"]), "hi");
+        assert_eq!(run_builtin(echo, &["--"]), "\n");
+        assert_eq!(run_builtin(echo, &["--", "-n"]), "-n\n");
+        assert_eq!(run_builtin(echo, &["-x"]), "-x\n");
+        assert_eq!(run_builtin(echo, &["-nx", "hi"]), "-nx hi\n");
+        assert_eq!(run_builtin(echo, &["-"]), "-\n");
+        assert_eq!(run_builtin(echo, &["hi", "-n"]), "hi -n\n");
+        assert_eq!(run_builtin(echo, &["-e", "a\\tb"]), "a\tb\n");
+        assert_eq!(run_builtin(echo, &["-eE", "a\\tb"]), "a\\tb\n");
+        assert_eq!(run_builtin(echo, &["-ne", "a\\nb"]), "a\nb");
+        assert_eq!(run_builtin(echo, &["-e", "hello\\cworld"]), "hello");
+        assert_eq!(run_builtin(echo, &["-e", "a\\c", "b"]), "a");
+        assert_eq!(run_builtin(echo, &["hello\\cworld"]), "hello\\cworld\n");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new("/a/b/../c/./d")), PathBuf::from("/a/c/d"));
+        assert_eq!(normalize_path(Path::new("/..")), PathBuf::from("/"));
+        assert_eq!(normalize_path(Path::new("/a/b/")), PathBuf::from("/a/b"));
+    }
+
+    #[test]
+    fn test_cd_and_pwd_with_symlink() {
+        let dir = temp_dir("pwd");
+        fs::create_dir_all(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let (state, _) = run_builtin_with(state, cd, &["link"]);
+        assert_eq!(state.pwd, dir.join("link"));
+
+        let (state, out) = run_builtin_with(state, pwd, &[]);
+        assert_eq!(out, format!("{}\n", dir.join("link").display()));
+        let (state, out) = run_builtin_with(state, pwd, &["-L"]);
+        assert_eq!(out, format!("{}\n", dir.join("link").display()));
+        let (state, out) = run_builtin_with(state, pwd, &["-P"]);
+        assert_eq!(out, format!("{}\n", fs::canonicalize(dir.join("real")).unwrap().display()));
+        let (state, out) = run_builtin_with(state, pwd, &["-PL"]);
+        assert_eq!(out, format!("{}\n", dir.join("link").display()));
+        let (state, out) = run_builtin_with(state, pwd, &["-x"]);
+        assert_eq!((state.last_status, out.as_str()), (2, ""));
+
+        let (state, _) = run_builtin_with(state, cd, &[".."]);
+        assert_eq!(state.pwd, dir);
+    }
+
+    #[test]
+    fn test_cd_history() {
+        let dir = temp_dir("cd_history");
+        for name in ["a", "b", "c"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let (state, out) = run_builtin_with(state, cd, &["-"]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+
+        let mut state = state;
+        state.last_status = 0;
+        let (state, _) = run_builtin_with(state, cd, &["a"]);
+        let (state, _) = run_builtin_with(state, cd, &["../b"]);
+        let (state, _) = run_builtin_with(state, cd, &["../c"]);
+        assert_eq!(state.dir_history, [dir.join("b"), dir.join("a"), dir.clone()]);
+
+        let (state, out) = run_builtin_with(state, cd, &["-2"]);
+        assert_eq!(state.pwd, dir.join("a"));
+        assert_eq!(out, format!("{}\n", dir.join("a").display()));
+
+        let (state, out) = run_builtin_with(state, cd, &["-"]);
+        assert_eq!(state.pwd, dir.join("c"));
+        assert_eq!(out, format!("{}\n", dir.join("c").display()));
+
+        let (state, _) = run_builtin_with(state, cd, &["+1"]);
+        assert_eq!(state.pwd, dir);
+
+        let (state, out) = run_builtin_with(state, cd, &["-9"]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+        assert_eq!(state.pwd, dir);
+    }
+
+    #[test]
+    fn test_cd_oldpwd() {
+        let dir = temp_dir("cd_oldpwd");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let (state, out) = run_builtin_with(state, cd, &["/tmp"]);
+        assert_eq!((state.pwd.as_path(), out.as_str()), (Path::new("/tmp"), ""));
+        assert_eq!(state.oldpwd, Some(dir.clone()));
+        assert_eq!(variable(&state, "OLDPWD"), Some(dir.display().to_string()));
+        assert!(state.exported.contains("OLDPWD"));
+
+        let (state, out) = run_builtin_with(state, cd, &["-"]);
+        assert_eq!(state.pwd, dir);
+        assert_eq!(out, format!("{}\n", dir.display()));
+        assert_eq!(state.oldpwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(variable(&state, "OLDPWD"), Some("/tmp".to_string()));
+    }
+
+    #[test]
+    fn test_pushd_popd() {
+        let dir = temp_dir("pushd_popd");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        state.variables.insert("HOME".to_string(), dir.display().to_string());
+
+        let (state, out) = run_builtin_with(state, pushd, &["a"]);
+        assert_eq!(state.pwd, dir.join("a"));
+        assert_eq!(out, "~/a ~\n");
+        let (state, out) = run_builtin_with(state, pushd, &["/tmp"]);
+        assert_eq!(out, "/tmp ~/a ~\n");
+        let (state, out) = run_builtin_with(state, dirs, &[]);
+        assert_eq!(out, "/tmp ~/a ~\n");
+
+        let (state, out) = run_builtin_with(state, popd, &[]);
+        assert_eq!(state.pwd, dir.join("a"));
+        assert_eq!(out, "~/a ~\n");
+        let (state, out) = run_builtin_with(state, popd, &[]);
+        assert_eq!(state.pwd, dir);
+        assert_eq!(out, "~\n");
+        assert_eq!(state.last_status, 0);
+
+        let (state, out) = run_builtin_with(state, popd, &[]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+        let mut state = state;
+        state.last_status = 0;
+        let (state, out) = run_builtin_with(state, pushd, &["missing"]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+        assert_eq!(state.pwd, dir);
+        assert!(state.dir_stack.is_empty());
+    }
+
+    #[test]
+    fn test_cd_errors() {
+        let dir = temp_dir("cd_errors");
+        fs::write(dir.join("file"), "").unwrap();
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let (state, _) = run_builtin_with(state, cd, &["file"]);
+        assert_eq!(state.pwd, dir);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(state, cd, &["missing"]);
+        assert_eq!(state.pwd, dir);
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut state = ShellState::default();
+        state.history = vec!["echo a".to_string(), "cd /tmp".to_string(), "history 2".to_string()];
+        let (state, out) = run_builtin_with(state, history, &[]);
+        assert_eq!(out, "    1  echo a\n    2  cd /tmp\n    3  history 2\n");
+        let (state, out) = run_builtin_with(state, history, &["2"]);
+        assert_eq!(out, "    2  cd /tmp\n    3  history 2\n");
+        let (state, out) = run_builtin_with(state, history, &["10"]);
+        assert_eq!(out.lines().count(), 3);
+        let (state, out) = run_builtin_with(state, history, &["x"]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+    }
+
+    #[test]
+    fn test_history_file() {
+        let dir = temp_dir("history_file");
+        let mut state = ShellState::default();
+        state.variables.insert("HOME".to_string(), dir.display().to_string());
+        load_history(&mut state);
+        assert!(state.history.is_empty());
+
+        add_history(&mut state, "echo a");
+        add_history(&mut state, "echo b");
+        save_history(&state);
+        assert_eq!(fs::read_to_string(dir.join(".shell_history")).unwrap(), "echo a\necho b\n");
+
+        let mut state = ShellState { history: Vec::new(), history_new: 0, ..state };
+        load_history(&mut state);
+        assert_eq!(state.history, ["echo a", "echo b"]);
+        add_history(&mut state, "echo c");
+        assert_eq!(state.history_new, 1);
+
+        state.variables.insert("HISTSIZE".to_string(), "2".to_string());
+        add_history(&mut state, "echo d");
+        assert_eq!(state.history, ["echo c", "echo d"]);
+        save_history(&state);
+        assert_eq!(fs::read_to_string(dir.join(".shell_history")).unwrap(), "echo c\necho d\n");
+
+        // another shell wrote the file meanwhile
+        state.variables.insert("HISTSIZE".to_string(), "3".to_string());
+        fs::write(dir.join(".shell_history"), "other\n").unwrap();
+        save_history(&state);
+        assert_eq!(fs::read_to_string(dir.join(".shell_history")).unwrap(), "echo c\necho d\n");
+        state.shopt.histappend = true;
+        fs::write(dir.join(".shell_history"), "other\n").unwrap();
+        save_history(&state);
+        assert_eq!(fs::read_to_string(dir.join(".shell_history")).unwrap(), "other\necho c\necho d\n");
+
+        state.variables.insert("HOME".to_string(), dir.join("missing").display().to_string());
+        load_history(&mut state);
+        assert_eq!(state.history, ["echo c", "echo d"]);
+    }
+
+    #[test]
+    fn test_command_candidates() {
+        let dir = temp_dir("command_candidates");
+        let tool = dir.join("echotool");
+        fs::write(&tool, "").unwrap();
+        fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        fs::write(dir.join("echoplain"), "").unwrap();
+        let mut state = ShellState::default();
+        state.variables.insert("PATH".to_string(), format!("{}:{}", dir.display(), dir.display()));
+
+        assert_eq!(command_candidates(&state, "ech"), ["echo", "echotool"]);
+        assert_eq!(command_candidates(&state, "echot"), ["echotool"]);
+        assert_eq!(command_candidates(&state, "pus"), ["pushd"]);
+        assert!(command_candidates(&state, "nosuch").is_empty());
+    }
+
+    #[test]
+    fn test_prompt() {
+        let state = ShellState::default();
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "$ ");
+        assert_eq!(render_prompt(&state, "PS2", "> "), "> ");
+
+        let state = eval(state, &args(&["PS1='[$?] $ '", ";", "false"]));
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "[1] $ ");
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "[1] $ ");
+        assert_eq!(state.last_status, 1);
+
+        let state = eval(state, &args(&["true"]));
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "[0] $ ");
+    }
+
+    #[test]
+    fn test_prompt_is_static() {
+        assert!(prompt_is_static("\\u@\\h:\\w\\$ "));
+        assert!(prompt_is_static("> "));
+        assert!(!prompt_is_static("[$?] "));
+        assert!(!prompt_is_static("`date` "));
+        assert!(!prompt_is_static("\\\\$HOME "));
+    }
+
+    #[test]
+    fn test_prompt_cache() {
+        let mut state = ShellState::default();
+        state.pwd = PathBuf::from("/tmp");
+        state.variables.insert("HOME".to_string(), "/home/tester".to_string());
+        state.variables.insert("PS1".to_string(), "\\w> ".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "/tmp> ");
+        assert_eq!(state.prompt_cache.borrow()["PS1"].rendered, "/tmp> ");
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "/tmp> ");
+
+        state.pwd = PathBuf::from("/home/tester/src");
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "~/src> ");
+        state.variables.insert("HOME".to_string(), "/home".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "~/tester/src> ");
+        state.variables.insert("PS1".to_string(), "\\w\\$ ".to_string());
+        let sign = if sys::is_root() { "#" } else { "$" };
+        assert_eq!(render_prompt(&state, "PS1", "$ "), format!("~/tester/src{} ", sign));
+        assert!(state.prompt_cache.borrow().get("PS1").is_some());
+
+        // anything expanded is rendered every time
+        state.variables.insert("PS1".to_string(), "[$?] ".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "[0] ");
+        assert!(state.prompt_cache.borrow().get("PS1").is_none());
+        state.last_status = 1;
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "[1] ");
+    }
+
+    #[test]
+    fn test_prompt_escapes() {
+        let mut state = ShellState::default();
+        state.pwd = PathBuf::from("/home/tester/src/it's");
+        state.variables.insert("HOME".to_string(), "/home/tester".to_string());
+        state.variables.insert("USER".to_string(), "tester".to_string());
+        let sign = if sys::is_root() { "#" } else { "$" };
+        let host = sys::hostname().unwrap();
+        let host = host.split('.').next().unwrap();
+
+        state.variables.insert("PS1".to_string(), "\\u@\\h:\\w\\$ ".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), format!("tester@{}:~/src/it's{} ", host, sign));
+
+        state.variables.insert("PS1".to_string(), "\\w\\n\\\\ \\q> ".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "~/src/it's\n\\ \\q> ");
+
+        state.pwd = PathBuf::from("/$HOME");
+        state.variables.insert("PS1".to_string(), "\\w ".to_string());
+        assert_eq!(render_prompt(&state, "PS1", "$ "), "/$HOME ");
+    }
+
+    #[test]
+    fn test_set_options() {
+        let (state, out) = run_builtin_with(ShellState::default(), set, &["-n"]);
+        assert!(state.options.noexec);
+        assert_eq!(out, "");
+        let (state, out) = run_builtin_with(state, set, &["-o"]);
+        assert_eq!(out, "monitor        \toff\nnoexec         \ton\n");
+        let (state, _) = run_builtin_with(state, set, &["+o", "noexec"]);
+        assert!(!state.options.noexec);
+
+        let (state, _) = run_builtin_with(state, set, &["-z"]);
+        assert_eq!(state.last_status, 2);
+        let (state, _) = run_builtin_with(state, set, &["-o", "nosuch"]);
+        assert_eq!(state.last_status, 2);
+    }
+
+    #[test]
+    fn test_noexec_only_checks() {
+        let dir = temp_dir("noexec");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        state.options.noexec = true;
+
+        let state = eval(state, &args(&["echo", "hi", ">", "out", ";", "cd", ".."]));
+        assert_eq!((state.last_status, state.pwd.as_path()), (0, dir.as_path()));
+        assert!(!dir.join("out").exists());
+
+        let state = eval(state, &args(&["echo", "|", "|", "cat"]));
+        assert_eq!(state.last_status, 2);
+
+        let mut state = state;
+        state.interactive = true;
+        let state = eval(state, &args(&["cd", ".."]));
+        assert_eq!(state.pwd, dir.parent().unwrap());
+    }
+
+    #[test]
+    fn test_stopwatch_toggle() {
+        let started = Instant::now();
+        let mut out = Vec::<u8>::new();
+        let state = stopwatch_toggle(ShellState::default(), started, &mut out);
+        assert_eq!(state.stopwatch, Some(started));
+        assert!(out.is_empty());
+
+        let state = stopwatch_toggle(state, started + Duration::from_millis(1500), &mut out);
+        assert_eq!(state.stopwatch, None);
+        assert_eq!(String::from_utf8(out).unwrap(), "1.500s\n");
+    }
+
+    #[test]
+    fn test_help_short() {
+        assert_eq!(run_builtin(help, &["-s", "echo"]), "echo: echo [-neE] [arg ...]\n");
+        assert_eq!(run_builtin(help, &["-s", "ec*"]), "echo: echo [-neE] [arg ...]\n");
+        assert_eq!(run_builtin(help, &["-s", "?d"]), "cd: cd [dir]\n");
+        assert_eq!(
+            run_builtin(help, &["-s", "e*"]),
+            "echo: echo [-neE] [arg ...]\nexit: exit [n]\nexport: export [-p] [name[=value] ...]\n"
+        );
+    }
+
+    #[test]
+    fn test_help_long() {
+        assert_eq!(
+            run_builtin(help, &["pwd"]),
+            "pwd: pwd [-LP]\n    Print the name of the current working directory.\n"
+        );
+        assert_eq!(run_builtin(help, &["nothing*"]), "");
+    }
+
+    fn file_redirect(fd: usize, target: &str, mode: RedirMode) -> Redirect {
+        Redirect { fd, target: RedirTarget::File(target.to_string(), mode) }
+    }
+
+    #[test]
+    fn test_words2proc() {
+        let argv = args(&["echo", "a", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.exec, "echo");
+        assert_eq!(result.argv, vec!["a", "b"]);
+        assert!(result.redirects.is_empty());
+
+        let argv = args(&["echo", "1", "2"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.exec, "echo");
+        assert_eq!(result.argv, vec!["1", "2"]);
+        assert!(result.redirects.is_empty());
+
+        let argv = args(&["echo", "a", ">", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.exec, "echo");
+        assert_eq!(result.argv, vec!["a"]);
+        assert_eq!(result.redirects, vec![file_redirect(1, "b", RedirMode::Write)]);
+
+        let argv = args(&["echo", "a", "2>", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.exec, "echo");
+        assert_eq!(result.argv, vec!["a"]);
+        assert_eq!(result.redirects, vec![file_redirect(2, "b", RedirMode::Write)]);
+
+        let argv = args(&["echo", "a", ">>", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.argv, vec!["a"]);
+        assert_eq!(result.redirects, vec![file_redirect(1, "b", RedirMode::Append)]);
+
+        let argv = args(&["echo", "a", "2", ">", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.argv, vec!["a", "2"]);
+        assert_eq!(result.redirects, vec![file_redirect(1, "b", RedirMode::Write)]);
+
+        let argv = args(&["echo", "a", "2>>", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.argv, vec!["a"]);
+        assert_eq!(result.redirects, vec![file_redirect(2, "b", RedirMode::Append)]);
+
+        let argv = args(&["echo", "a", "2>", "b", ">", "c"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.redirects, vec![
+            file_redirect(2, "b", RedirMode::Write),
+            file_redirect(1, "c", RedirMode::Write),
+        ]);
+
+        let argv = args(&["echo", "'>'", "b"]);
+        let result = words2proc(&argv).unwrap();
+        assert_eq!(result.argv, vec![">", "b"]);
+        assert!(result.redirects.is_empty());
+
+        let argv = args(&["echo", "a", ">"]);
+        assert!(words2proc(&argv).is_none());
+    }
+
+    #[test]
+    fn test_words2proc_dup() {
+        let result = words2proc(&args(&["cmd", ">", "out", "2>&", "1"])).unwrap();
+        assert!(result.argv.is_empty());
+        assert_eq!(result.redirects, vec![
+            file_redirect(1, "out", RedirMode::Write),
+            Redirect { fd: 2, target: RedirTarget::Fd(1) },
+        ]);
+
+        let result = words2proc(&args(&["cmd", ">&", "2"])).unwrap();
+        assert_eq!(result.redirects, vec![Redirect { fd: 1, target: RedirTarget::Fd(2) }]);
+
+        let result = words2proc(&args(&["cmd", ">&", "out"])).unwrap();
+        assert_eq!(result.redirects, vec![
+            file_redirect(1, "out", RedirMode::Write),
+            Redirect { fd: 2, target: RedirTarget::Fd(1) },
+        ]);
+
+        assert!(words2proc(&args(&["cmd", "2>&", "out"])).is_none());
+    }
+
+    #[test]
+    fn test_words2proc_stdin() {
+        let result = words2proc(&args(&["cat", "<", "input.txt"])).unwrap();
+        assert_eq!(result.exec, "cat");
+        assert!(result.argv.is_empty());
+        assert_eq!(result.redirects, vec![file_redirect(0, "input.txt", RedirMode::Read)]);
+
+        assert!(words2proc(&args(&["cat", "<"])).is_none());
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = temp_dir("export");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["export", "FOO=bar"]));
+        let state = eval(state, &args(&["echo", "$FOO", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "bar\n");
+
+        let state = eval(state, &args(&["sh", "-c", "'echo $FOO'", ">", "child"]));
+        assert_eq!(fs::read_to_string(dir.join("child")).unwrap(), "bar\n");
+        assert!(env::var("FOO").is_err());
+
+        let (state, out) = run_builtin_with(state, export, &[]);
+        assert!(out.contains("declare -x FOO=\"bar\"\n"));
+
+        let (state, _) = run_builtin_with(state, export, &["QUOTED=a\"$b"]);
+        let (state, out) = run_builtin_with(state, export, &["-p"]);
+        assert!(out.contains("declare -x QUOTED=\"a\\\"\\$b\"\n"));
+
+        let (state, _) = run_builtin_with(state, export, &["1x=y"]);
+        assert_eq!(state.last_status, 1);
+        assert!(!state.variables.contains_key("1x"));
+    }
+
+    #[test]
+    fn test_words2proc_assignments() {
+        let result = words2proc(&args(&["A=1", "B='x y'", "cmd", "C=2"])).unwrap();
+        assert_eq!(result.assignments, vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "x y".to_string()),
+        ]);
+        assert_eq!(result.exec, "cmd");
+        assert_eq!(result.argv, vec!["C=2"]);
+
+        let result = words2proc(&args(&["'A=1'", "1B=2"])).unwrap();
+        assert!(result.assignments.is_empty());
+        assert_eq!(result.exec, "A=1");
+
+        let result = words2proc(&args(&["A=1"])).unwrap();
+        assert_eq!(result.exec, "");
+        assert_eq!(result.assignments.len(), 1);
+    }
+
+    #[test]
+    fn test_assignment_prefix() {
+        let dir = temp_dir("assignment");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["ONE_SHOT=bar", "sh", "-c", "'echo $ONE_SHOT'", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "bar\n");
+        assert!(!state.variables.contains_key("ONE_SHOT"));
+
+        let state = eval(state, &args(&["KEPT=baz"]));
+        assert_eq!(state.variables.get("KEPT").map(String::as_str), Some("baz"));
+        let state = eval(state, &args(&["echo", "$KEPT", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "baz\n");
+
+        // not exported, so children do not see it
+        let state = eval(state, &args(&["sh", "-c", "'echo \"[$KEPT]\"'", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[]\n");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_words2list() {
+        let tokens = args(&["a", "&&", "b", "|", "c", "||", "d"]);
+        let list = words2list(&tokens).unwrap();
+        assert_eq!(list, vec![
+            (Connector::Always, &tokens[0..1], false),
+            (Connector::And, &tokens[2..5], false),
+            (Connector::Or, &tokens[6..7], false),
+        ]);
+
+        assert_eq!(words2list(&args(&["&&", "b"])).err(), Some("&&"));
+        assert_eq!(words2list(&args(&["a", "||", "||", "b"])).err(), Some("||"));
+        assert_eq!(words2list(&args(&["a", "&&"])).err(), Some("newline"));
+        assert_eq!(words2list(&args(&["a", "&&", "|", "b"])).err(), Some("|"));
+        for word in CLOSING_RESERVED_WORDS {
+            assert_eq!(words2list(&args(&[word])).err(), Some(word));
+            assert_eq!(words2list(&args(&["a", "|", word, "x"])).err(), Some(word));
+        }
+        assert!(words2list(&args(&["echo", "done", "fi"])).is_ok());
+        assert!(words2list(&args(&["'done'"])).is_ok());
+        assert!(words2list(&args(&["x=1", "done"])).is_ok());
+
+        let tokens = args(&["a", ";", ";", "b", ";"]);
+        let list = words2list(&tokens).unwrap();
+        assert_eq!(list, vec![(Connector::Always, &tokens[0..1], false), (Connector::Always, &tokens[3..4], false)]);
+        assert_eq!(words2list(&args(&[";"])).unwrap(), vec![]);
+
+        let tokens = args(&["a", "&", "b", "&", ";", "c"]);
+        let list = words2list(&tokens).unwrap();
+        assert_eq!(list, vec![
+            (Connector::Always, &tokens[0..1], true),
+            (Connector::Always, &tokens[2..3], true),
+            (Connector::Always, &tokens[5..6], false),
+        ]);
+        assert_eq!(words2list(&args(&["&", "a"])).err(), Some("&"));
+        assert_eq!(words2list(&args(&["a", "&", "&"])).err(), Some("&"));
+        assert_eq!(words2list(&args(&["a", "&&", "&"])).err(), Some("&"));
+        assert_eq!(words2list(&args(&["a", "&&", ";", "b"])).err(), Some(";"));
+        assert_eq!(words2list(&args(&["a", ";", "&&", "b"])).err(), Some("&&"));
+    }
+
+    #[test]
+    fn test_background_job() {
+        let started = Instant::now();
+        let state = eval(ShellState::default(), &args(&["sleep", "5", "&", "true", "&"]));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(state.last_status, 0);
+        assert_eq!(state.jobs.len(), 2);
+        assert_eq!((state.jobs[0].number, state.jobs[0].command.as_str()), (1, "sleep 5"));
+        assert_eq!(state.jobs[1].number, 2);
+
+        let mut state = state;
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut done = Vec::new();
+        while done.is_empty() && Instant::now() < deadline {
+            done = reap_jobs(&mut state);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(done.iter().map(|job| job.number).collect::<Vec<_>>(), [2]);
+        assert_eq!(state.jobs.len(), 1);
+
+        let sleeper = state.jobs[0].pids[0];
+        sys::send_signal(sleeper, sys::SIGTERM).unwrap();
+        while reap_jobs(&mut state).is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(state.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_jobs_builtin() {
+        let mut state = eval(ShellState::default(), &args(&["sleep", "5", "&", "true", "&"]));
+        let sleeper = state.jobs[0].pids[0];
+        let mut out = String::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !out.contains("Done") && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            (state, out) = run_builtin_with(state, jobs, &[]);
+        }
+        assert_eq!(out, "[1]  Running\tsleep 5\n[2]  Done\ttrue\n");
+        let (mut state, out) = run_builtin_with(state, jobs, &[]);
+        assert_eq!(out, "[1]  Running\tsleep 5\n");
+
+        sys::send_signal(sleeper, sys::SIGTERM).unwrap();
+        while reap_jobs(&mut state).is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_source_relative() {
+        let dir = temp_dir("source_relative");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("outer.sh"), "x=1\nsource sub/inner.sh\n. lib.sh\necho $x $y \\\n  \"$z\" > out\n").unwrap();
+        fs::write(dir.join("sub/inner.sh"), "# from sub\ny=2\n").unwrap();
+        fs::write(dir.join("bin/lib.sh"), "z='3\n4'\n").unwrap();
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        state.variables.insert("PATH".to_string(), format!("{}:/usr/bin:/bin", dir.join("bin").display()));
+
+        let (state, _) = run_builtin_with(state, source, &["outer.sh"]);
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "1 2 3\n4\n");
+        assert_eq!(state.last_status, 0);
+
+        let (state, _) = run_builtin_with(state, source, &["missing.sh"]);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(state, source, &[]);
+        assert_eq!(state.last_status, 2);
+
+        fs::write(dir.join("exits.sh"), "false\nexit\necho not reached > out\n").unwrap();
+        let (state, _) = run_builtin_with(state, source, &["./exits.sh"]);
+        assert_eq!(state.exit_code, Some(1));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "1 2 3\n4\n");
+    }
+
+    #[test]
+    fn test_sequential_list() {
+        let dir = temp_dir("sequence");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["false", ";", "echo", "$?", ">", "out", ";", "echo", "b", ">>", "out", ";"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "1\nb\n");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_and_or_lists() {
+        let dir = temp_dir("and_or");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["false", "&&", "echo", "no", ">", "and"]));
+        assert!(!dir.join("and").exists());
+        assert_eq!(state.last_status, 1);
+
+        let state = eval(state, &args(&["false", "||", "echo", "yes", ">", "or"]));
+        assert_eq!(fs::read_to_string(dir.join("or")).unwrap(), "yes\n");
+
+        let state = eval(state, &args(&["false", "&&", "echo", "a", "||", "echo", "b", ">", "chain"]));
+        assert_eq!(fs::read_to_string(dir.join("chain")).unwrap(), "b\n");
+
+        let state = eval(state, &args(&["mkdir", "x", "&&", "cd", "x"]));
+        assert_eq!(state.pwd, dir.join("x"));
+
+        let state = eval(state, &args(&["false", "||", "echo", "$?", ">", "status"]));
+        assert_eq!(fs::read_to_string(dir.join("x").join("status")).unwrap(), "1\n");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let dir = temp_dir("substitution");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["echo", "$(echo hi)", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "hi\n");
+
+        let state = eval(state, &args(&["echo", "\"[$(printf 'a\\n\\n')]\"", "`echo b`", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[a] b\n");
+
+        let state = eval(state, &args(&["echo", "$(cd sub; pwd)", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), format!("{}\n", dir.join("sub").display()));
+        assert_eq!(state.pwd, dir);
+
+        let state = eval(state, &args(&["echo", "$(echo $(echo nested))", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "nested\n");
+        assert_eq!(state.exit_code, None);
+    }
+
+    #[test]
+    fn test_glob_words() {
+        let dir = temp_dir("glob_words");
+        for file in ["a.rs", "b.rs", "c.txt", "it's.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["echo", "*.rs", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "a.rs b.rs it's.rs\n");
+
+        let state = eval(state, &args(&["echo", "'*.rs'", "*.none", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "*.rs *.none\n");
+
+        let mut state = state;
+        state.shopt.nullglob = true;
+        let state = eval(state, &args(&["echo", "x", "*.none", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "x\n");
+
+        let words = expand_words(&state, &args(&["A=*.txt", "echo", "*.txt"])).unwrap();
+        assert_eq!(words, args(&["A=*.txt", "echo", "c.txt"]));
+    }
+
+    #[test]
+    fn test_nul_is_dropped() {
+        let dir = temp_dir("nul");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let state = eval(state, &args(&["x=\"$(printf 'a\\0b')\"", ";", "printf", "'[%s]'", "\"$x\"", "c\0d", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[ab][cd]");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_assignment_is_not_split() {
+        let dir = temp_dir("assignment_split");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        fs::write(dir.join("a.txt"), "").unwrap();
+
+        let state = eval(state, &args(&["x=$(echo 'a  b')", "y=*.txt", ";", "printf", "'[%s]'", "\"$x\"", "$y", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[a  b][a.txt]");
+        assert_eq!(variable(&state, "y"), Some("*.txt".to_string()));
+
+        let state = eval(state, &args(&["printf", "'[%s]'", "$(echo a  b)", "$x", "$empty", "\"$empty\"", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[a][b][a][b][]");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_expansion_order() {
+        let dir = temp_dir("expansion_order");
+        for file in ["a.txt", "b.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        state.variables.insert("HOME".to_string(), "/home/tester".to_string());
+        let state = eval(state, &args(&["x='*.txt'", ";", "t='~'", ";", "d='$x'"]));
+
+        // values are globbed when unquoted, but never expanded again
+        let words = expand_words(&state, &args(&["echo", "\"$x\"", "$x", "$t", "$d"])).unwrap();
+        assert_eq!(words, args(&["echo", "\"*.txt\"", "a.txt", "b.txt", "~", "$x"]));
+
+        // the tilde comes first, and its value is not globbed either
+        let mut state = state;
+        state.variables.insert("HOME".to_string(), "/*".to_string());
+        assert_eq!(expand_words(&state, &args(&["~"])).unwrap(), args(&["\"/*\""]));
+
+        let state = eval(state, &args(&["echo", "\"$x\"", "$x", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "*.txt a.txt b.txt\n");
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_read_fields() {
+        let ifs = " \t\n";
+        assert_eq!(read_fields("  a  b c  ", ifs, 1, false), args(&["a  b c"]));
+        assert_eq!(read_fields(" a  b c ", ifs, 2, false), args(&["a", "b c"]));
+        assert_eq!(read_fields("a", ifs, 3, false), args(&["a", "", ""]));
+        assert_eq!(read_fields("a\\ b c", ifs, 2, false), args(&["a b", "c"]));
+        assert_eq!(read_fields("a\\ b c", ifs, 2, true), args(&["a\\", "b c"]));
+        assert_eq!(read_fields("a : b::c", " :", 4, false), args(&["a", "b", "", "c"]));
+        assert_eq!(read_fields(" a b ", "", 2, false), args(&[" a b ", ""]));
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = temp_dir("read");
+        fs::write(dir.join("input"), "one two  three\nfour\\\nfive\nlast").unwrap();
+        let mut state = ShellState::default();
+        state.stdin = Some(Rc::new(File::open(dir.join("input")).unwrap()));
+
+        let (state, _) = run_builtin_with(state, read, &["a", "b"]);
+        assert_eq!((state.variables["a"].as_str(), state.variables["b"].as_str()), ("one", "two  three"));
+        let (state, _) = run_builtin_with(state, read, &[]);
+        assert_eq!(state.variables["REPLY"], "fourfive");
+        let (state, _) = run_builtin_with(state, read, &["-r", "x"]);
+        assert_eq!((state.variables["x"].as_str(), state.last_status), ("last", 1));
+        let (state, _) = run_builtin_with(state, read, &["a-b"]);
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_read_from_pipeline() {
+        let proc = words2proc(&args(&["read", "x"])).unwrap();
+        let (state, _, _) = run_stage(ShellState::default(), proc, StageInput::Buffer(b"piped\n".to_vec()), true);
+        assert_eq!((state.variables["x"].as_str(), state.last_status), ("piped", 0));
+
+        let (read, mut write) = sys::pipe().unwrap();
+        write.write_all(b"from pipe").unwrap();
+        drop(write);
+        let proc = words2proc(&args(&["read", "x"])).unwrap();
+        let (state, _, _) = run_stage(state, proc, StageInput::Pipe(read), true);
+        assert_eq!((state.variables["x"].as_str(), state.last_status), ("from pipe", 1));
+    }
+
+    #[test]
+    fn test_unset() {
+        let dir = temp_dir("unset");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let state = eval(state, &args(&["a=1", ";", "b=2", ";", "export", "b"]));
+        let (state, _) = run_builtin_with(state, unset, &["-v", "a", "b", "HOME"]);
+        assert_eq!(state.last_status, 0);
+        assert_eq!(variable(&state, "a"), None);
+        assert_eq!(variable(&state, "b"), None);
+        assert!(!state.exported.contains("b"));
+        // the inherited variable is gone for the shell and its commands, not for the process
+        assert_eq!(variable(&state, "HOME"), None);
+        assert!(env::var("HOME").is_ok());
+        let state = eval(state, &args(&["sh", "-c", "'echo \"[$HOME]\" > out'"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "[]\n");
+        let (state, out) = run_builtin_with(state, export, &[]);
+        assert!(!out.contains("declare -x HOME="));
+
+        let state = eval(state, &args(&["c=1"]));
+        let (state, _) = run_builtin_with(state, unset, &["-f", "c"]);
+        assert_eq!(variable(&state, "c"), Some("1".to_string()));
+        let (state, _) = run_builtin_with(state, unset, &["c"]);
+        assert_eq!(variable(&state, "c"), None);
+
+        let (state, _) = run_builtin_with(state, unset, &["1x"]);
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_readonly() {
+        let dir = temp_dir("readonly");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let (state, _) = run_builtin_with(state, readonly, &["a=1 \"x\"", "b"]);
+        assert_eq!(state.last_status, 0);
+        let (state, _) = run_builtin_with(state, export, &["b"]);
+        let (state, out) = run_builtin_with(state, readonly, &["-p"]);
+        assert_eq!(out, "declare -r a=\"1 \\\"x\\\"\"\ndeclare -rx b\n");
+
+        let state = eval(state, &args(&["a=2"]));
+        assert_eq!(state.last_status, 1);
+        let state = eval(state, &args(&["a=2", "echo", "ran", ">", "out"]));
+        assert_eq!(state.last_status, 1);
+        assert!(!dir.join("out").exists());
+        let (state, _) = run_builtin_with(state, export, &["a=3"]);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(state, unset, &["a"]);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(state, readonly, &["a=4"]);
+        assert_eq!(state.last_status, 1);
+        assert_eq!(variable(&state, "a"), Some("1 \"x\"".to_string()));
+
+        let (state, _) = run_builtin_with(state, readonly, &["-f", "a"]);
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_kill_list() {
+        let out = run_builtin(kill, &["-l"]);
+        assert!(out.starts_with(" 1) SIGHUP\t"));
+        assert!(out.contains("15) SIGTERM"));
+        assert_eq!(run_builtin(kill, &["-l", "15", "TERM", "137"]), "TERM\n15\nKILL\n");
+
+        let (state, out) = run_builtin_with(ShellState::default(), kill, &["-l", "NOPE"]);
+        assert_eq!((state.last_status, out.as_str()), (1, ""));
+    }
+
+    #[test]
+    fn test_kill_process() {
+        for spec in [&["-9"][..], &["-s", "KILL"], &["-n", "9"]] {
+            let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+            let mut argv = spec.to_vec();
+            let pid = child.id().to_string();
+            argv.push(&pid);
+            let (state, _) = run_builtin_with(ShellState::default(), kill, &argv);
+            assert_eq!(state.last_status, 0);
+            assert_eq!(child.wait().unwrap().signal(), Some(9));
+        }
+
+        let (state, _) = run_builtin_with(ShellState::default(), kill, &["-BOGUS", "1"]);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(ShellState::default(), kill, &["abc"]);
+        assert_eq!(state.last_status, 1);
+        let (state, _) = run_builtin_with(ShellState::default(), kill, &[]);
+        assert_eq!(state.last_status, 2);
+    }
+
+    #[test]
+    fn test_tilde_expansion() {
+        let dir = temp_dir("tilde");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        state.variables.insert("HOME".to_string(), "/home/tester".to_string());
+
+        let state = eval(state, &args(&["echo", "~", "~/foo", "'~'", "a~", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "/home/tester /home/tester/foo ~ a~\n");
+
+        let root = sys::home_dir("root").unwrap();
+        let state = eval(state, &args(&["echo", "~root/x", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), format!("{}/x\n", root));
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let state = eval(ShellState::default(), &args(&["exit", "4"]));
+        assert_eq!(state.exit_code, Some(4));
+
+        let mut state = ShellState::default();
+        state.last_status = 2;
+        let state = eval(state, &args(&["exit"]));
+        assert_eq!(state.exit_code, Some(2));
+    }
+
+    #[test]
+    fn test_dup_order() {
+        let dir = temp_dir("dup_order");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let script = "echo out; echo err >&2";
+
+        let state = eval(state, &args(&["sh", "-c", &format!("'{}'", script), ">", "both", "2>&", "1"]));
+        assert_eq!(fs::read_to_string(dir.join("both")).unwrap(), "out\nerr\n");
+
+        // stderr is duplicated before stdout moves, so it does not follow it
+        let state = eval(state, &args(&["sh", "-c", &format!("'{}'", script), "2>", "err", "2>&", "1", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "out\n");
+        assert_eq!(fs::read_to_string(dir.join("err")).unwrap(), "");
+
+        let state = eval(state, &args(&["sh", "-c", &format!("'{}'", script), "2>&", "1", "|", "sort", ">", "piped"]));
+        assert_eq!(fs::read_to_string(dir.join("piped")).unwrap(), "err\nout\n");
+
+        let state = eval(state, &args(&["echo", "hi", ">", "echoed", "2>&", "1"]));
+        assert_eq!(fs::read_to_string(dir.join("echoed")).unwrap(), "hi\n");
+
+        // builtins write their errors wherever stderr was sent as well
+        let state = eval(state, &args(&["cd", "missing", ">", "builtin", "2>&", "1"]));
+        assert_eq!(fs::read_to_string(dir.join("builtin")).unwrap(), "shell: cd: missing: No such file or directory\n");
+        let state = eval(state, &args(&["cd", "missing", "2>&", "1", "|", "cat", ">", "builtin"]));
+        assert_eq!(fs::read_to_string(dir.join("builtin")).unwrap(), "shell: cd: missing: No such file or directory\n");
+
+        let state = eval(state, &args(&["echo", "hi", ">&", "3"]));
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_input_redirect() {
+        let dir = temp_dir("stdin");
+        fs::write(dir.join("input.txt"), "from file\n").unwrap();
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+
+        let state = eval(state, &args(&["cat", "<", "input.txt", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "from file\n");
+
+        let state = eval(state, &args(&["echo", "piped", "|", "cat", "<", "input.txt", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "from file\n");
+
+        let state = eval(state, &args(&["cat", "<", "missing.txt"]));
+        assert_eq!(state.last_status, 1);
+    }
+
+    #[test]
+    fn test_open_redirects_error() {
+        let dir = temp_dir("redirect_error");
+        let proc = words2proc(&args(&["cat", "<", "missing.txt"])).unwrap();
+        let Err(message) = open_redirects(&dir, &proc) else {
+            panic!("missing input should fail");
+        };
+        assert_eq!(message, "missing.txt: No such file or directory");
+    }
+
+    #[test]
+    fn test_words2pipeline() {
+        let pipeline = words2pipeline(&args(&["echo", "hi", "|", "cat", "|", "wc", "-l"])).unwrap();
+        assert_eq!(pipeline.len(), 3);
+        assert_eq!(pipeline[0].exec, "echo");
+        assert_eq!(pipeline[0].argv, vec!["hi"]);
+        assert_eq!(pipeline[1].exec, "cat");
+        assert_eq!(pipeline[2].exec, "wc");
+        assert_eq!(pipeline[2].argv, vec!["-l"]);
+
+        let pipeline = words2pipeline(&args(&["echo", "'|'", "x"])).unwrap();
+        assert_eq!(pipeline.len(), 1);
+        assert_eq!(pipeline[0].argv, vec!["|", "x"]);
+
+        assert_eq!(words2pipeline(&args(&["echo", "|"])).err(), Some("|"));
+        assert_eq!(words2pipeline(&args(&["|", "cat"])).err(), Some("|"));
+        assert_eq!(words2pipeline(&args(&["echo", ">"])).err(), Some("newline"));
+    }
+
+    #[test]
+    fn test_pipeline_to_file() {
+        let dir = temp_dir("pipeline");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let state = eval(state, &args(&["echo", "hi", "|", "cat", ">", "out"]));
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "hi\n");
+        assert_eq!(state.last_status, 0);
+
+        let state = eval(state, &args(&["echo", "a", "b", "c", "|", "tr", "' '", "'\\n'", "|", "wc", "-l", ">", "count"]));
+        assert_eq!(fs::read_to_string(dir.join("count")).unwrap().trim(), "3");
+
+        let state = eval(state, &args(&["echo", "x", "|", "false"]));
+        assert_eq!(state.last_status, 1);
+        let state = eval(state, &args(&["false", "|", "echo", "x", ">", "out"]));
+        assert_eq!(state.last_status, 0);
+    }
+
+    #[test]
+    fn test_pipeline_builtins_run_in_subshells() {
+        let dir = temp_dir("pipeline_subshell");
+        let mut state = ShellState::default();
+        state.pwd = dir.clone();
+        let state = eval(state, &args(&["cd", "/", "|", "cat"]));
+        assert_eq!(state.pwd, dir);
+        let state = eval(state, &args(&["export", "x=1", "|", "cat", ";", "y=2", "|", "cat"]));
+        assert!(!state.variables.contains_key("x") && !state.variables.contains_key("y"));
+        let state = eval(state, &args(&["echo", "hi", "|", "exit", "3"]));
+        assert_eq!((state.exit_code, state.last_status), (None, 3));
+        let state = eval(state, &args(&["cd", "/", "&"]));
+        assert_eq!(state.pwd, dir);
+        let state = eval(state, &args(&["cd", "/"]));
+        assert_eq!(state.pwd, Path::new("/"));
     }
 }
 
@@ -0,0 +1,133 @@
+/**
+* options managed by the `shopt` builtin
+*/
+#[derive(Debug, Default, Clone)]
+pub struct ShoptOptions {
+    pub autocd: bool,
+    // accepted, but `cd` does not correct spelling yet
+    pub cdspell: bool,
+    // accepted, but LINES and COLUMNS are not updated yet
+    pub checkwinsize: bool,
+    pub dotglob: bool,
+    // accepted, but globbing knows no extended patterns yet
+    pub extglob: bool,
+    pub histappend: bool,
+    pub nullglob: bool,
+}
+
+impl ShoptOptions {
+    pub const NAMES: [&'static str; 7] = [
+        "autocd",
+        "cdspell",
+        "checkwinsize",
+        "dotglob",
+        "extglob",
+        "histappend",
+        "nullglob",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let value = match name {
+            "autocd" => self.autocd,
+            "cdspell" => self.cdspell,
+            "checkwinsize" => self.checkwinsize,
+            "dotglob" => self.dotglob,
+            "extglob" => self.extglob,
+            "histappend" => self.histappend,
+            "nullglob" => self.nullglob,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let value = match name {
+            "autocd" => &mut self.autocd,
+            "cdspell" => &mut self.cdspell,
+            "checkwinsize" => &mut self.checkwinsize,
+            "dotglob" => &mut self.dotglob,
+            "extglob" => &mut self.extglob,
+            "histappend" => &mut self.histappend,
+            "nullglob" => &mut self.nullglob,
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/**
+* options managed by the `set` builtin
+*/
+#[derive(Debug, Default, Clone)]
+pub struct ShellOptions {
+    // keep a job table and report on background jobs
+    pub monitor: bool,
+    // read commands without executing them
+    pub noexec: bool,
+}
+
+impl ShellOptions {
+    // names with the single letter flag each one is set by
+    pub const NAMES: [(&'static str, char); 2] = [
+        ("monitor", 'm'),
+        ("noexec", 'n'),
+    ];
+
+    pub fn name_of(flag: char) -> Option<&'static str> {
+        Self::NAMES.iter().find(|(_, f)| *f == flag).map(|(name, _)| *name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let value = match name {
+            "monitor" => self.monitor,
+            "noexec" => self.noexec,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let value = match name {
+            "monitor" => &mut self.monitor,
+            "noexec" => &mut self.noexec,
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_known() {
+        let options = ShoptOptions::default();
+        for name in ShoptOptions::NAMES {
+            assert_eq!(options.get(name), Some(false));
+        }
+        assert_eq!(options.get("unknown"), None);
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut options = ShoptOptions::default();
+        *options.get_mut("nullglob").unwrap() = true;
+        assert!(options.nullglob);
+        assert_eq!(options.get("nullglob"), Some(true));
+        assert!(options.get_mut("unknown").is_none());
+    }
+
+    #[test]
+    fn test_shell_options() {
+        let mut options = ShellOptions::default();
+        for (name, flag) in ShellOptions::NAMES {
+            assert_eq!(options.get(name), Some(false));
+            assert_eq!(ShellOptions::name_of(flag), Some(name));
+        }
+        *options.get_mut("noexec").unwrap() = true;
+        assert!(options.noexec);
+        assert_eq!(ShellOptions::name_of('z'), None);
+        assert!(options.get_mut("unknown").is_none());
+    }
+}
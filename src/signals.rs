@@ -0,0 +1,120 @@
+/**
+* signal names without the `SIG` prefix and their numbers, which differ between systems.
+* linux has STKFLT and PWR where the bsds have EMT and INFO
+*/
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+    ("EMT", libc::SIGEMT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    #[cfg(all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"))))]
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    ("PWR", libc::SIGPWR),
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+    ("INFO", libc::SIGINFO),
+    ("SYS", libc::SIGSYS),
+];
+
+/**
+* the number of a signal given as `TERM`, `SIGTERM` or `term`
+*/
+pub fn name_to_num(name: &str) -> Option<i32> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|&(_, num)| num)
+}
+
+/**
+* the name of signal `num`, without the `SIG` prefix
+*/
+pub fn num_to_name(num: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|&&(_, n)| n == num).map(|&(name, _)| name)
+}
+
+/**
+* the number of a signal given either by name or by number
+*/
+pub fn parse(spec: &str) -> Option<i32> {
+    match spec.parse::<i32>() {
+        Ok(0) => Some(0),
+        Ok(num) => num_to_name(num).map(|_| num),
+        Err(_) => name_to_num(spec),
+    }
+}
+
+/**
+* every signal as `kill -l` prints them, by number and five to a line
+*/
+pub fn table() -> String {
+    let mut signals = SIGNALS.to_vec();
+    signals.sort_by_key(|&(_, num)| num);
+    let mut text = String::new();
+    for (index, (name, num)) in signals.iter().enumerate() {
+        text.push_str(&format!("{:>2}) SIG{}", num, name));
+        text.push(if (index + 1) % 5 == 0 || index + 1 == signals.len() { '\n' } else { '\t' });
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(name_to_num("TERM"), Some(15));
+        assert_eq!(num_to_name(15), Some("TERM"));
+        for &(name, num) in SIGNALS {
+            assert_eq!((name_to_num(name), num_to_name(num)), (Some(num), Some(name)));
+        }
+    }
+
+    #[test]
+    fn test_spellings() {
+        assert_eq!(name_to_num("SIGKILL"), Some(9));
+        assert_eq!(name_to_num("int"), Some(2));
+        assert_eq!(name_to_num("NOPE"), None);
+        assert_eq!(num_to_name(0), None);
+        assert_eq!(num_to_name(32), None);
+        assert_eq!(parse("9"), Some(9));
+        assert_eq!(parse("HUP"), Some(1));
+        assert_eq!(parse("0"), Some(0));
+        assert_eq!(parse("99"), None);
+    }
+
+    #[test]
+    fn test_table() {
+        let table = table();
+        assert!(table.starts_with(" 1) SIGHUP\t 2) SIGINT\t 3) SIGQUIT\t 4) SIGILL\t 5) SIGTRAP\n"));
+        #[cfg(target_os = "linux")]
+        assert!(table.ends_with("31) SIGSYS\n"));
+        assert_eq!(table.lines().count(), SIGNALS.len().div_ceil(5));
+    }
+}
@@ -0,0 +1,187 @@
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::FromRawFd;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+pub use libc::{SIGINT, SIGTERM};
+
+/**
+* a field of the passwd entry `lookup` finds, given the entry, a buffer and where to put the result
+*/
+fn passwd_field(
+    lookup: impl FnOnce(*mut libc::passwd, *mut c_char, usize, *mut *mut libc::passwd) -> c_int,
+    field: fn(&libc::passwd) -> *mut c_char,
+) -> Option<String> {
+    // null pointers and zeros, until the lookup fills it in
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let status = lookup(&mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+    let value = field(&pwd);
+    if status != 0 || result.is_null() || value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(value) }.to_string_lossy().into_owned())
+}
+
+/**
+* the home directory of `user` from the passwd database
+*/
+pub fn home_dir(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    passwd_field(
+        |pwd, buf, len, result| unsafe { libc::getpwnam_r(name.as_ptr(), pwd, buf, len, result) },
+        |pwd| pwd.pw_dir,
+    )
+}
+
+/**
+* the name of the effective user
+*/
+pub fn user_name() -> Option<String> {
+    let uid = unsafe { libc::geteuid() };
+    passwd_field(
+        |pwd, buf, len, result| unsafe { libc::getpwuid_r(uid, pwd, buf, len, result) },
+        |pwd| pwd.pw_name,
+    )
+}
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+pub fn hostname() -> Option<String> {
+    let mut buf = [0 as c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+}
+
+/**
+* create a pipe as (read end, write end).
+* both ends are close-on-exec so only the fds a child is given survive exec
+*/
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds: [c_int; 2] = [0; 2];
+    if unsafe { open_pipe(&mut fds) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn open_pipe(fds: &mut [c_int; 2]) -> c_int {
+    libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC)
+}
+
+/**
+* without pipe2, close-on-exec is set after the pipe is made.
+* commands are only spawned from the main thread, so none can inherit it in between
+*/
+#[cfg(not(target_os = "linux"))]
+unsafe fn open_pipe(fds: &mut [c_int; 2]) -> c_int {
+    if libc::pipe(fds.as_mut_ptr()) != 0 {
+        return -1;
+    }
+    for fd in *fds {
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) != 0 {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            return -1;
+        }
+    }
+    0
+}
+
+/**
+* whether the current user may execute `path`, as decided by access(2)
+*/
+pub fn is_executable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::X_OK) == 0 }
+}
+
+/**
+* whether the child `pid` has exited, reaping it if so without waiting for it
+*/
+pub fn try_reap(pid: u32) -> bool {
+    let mut status: c_int = 0;
+    match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } {
+        0 => false,
+        -1 => io::Error::last_os_error().raw_os_error() == Some(libc::ECHILD),
+        _ => true,
+    }
+}
+
+extern "C" fn ignore_signal(_: c_int) {}
+
+/**
+* keep SIGINT from killing the shell.
+* it is caught rather than ignored, so the commands the shell runs get the default again
+*/
+pub fn catch_interrupt() {
+    let handler: extern "C" fn(c_int) = ignore_signal;
+    unsafe { libc::signal(SIGINT, handler as libc::sighandler_t) };
+}
+
+/**
+* send `sig` to the process `pid`
+*/
+pub fn send_signal(pid: u32, sig: c_int) -> io::Result<()> {
+    let result = unsafe { libc::kill(pid as libc::pid_t, sig) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/**
+* the terminal on standard input in non-canonical mode without echo or signals,
+* so ctrl-c is read as a byte. it is as it was before once this is dropped
+*/
+pub struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        // every field is an integer, so all zeros is a valid value to fill in
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_interrupt() {
+        catch_interrupt();
+        send_signal(std::process::id(), SIGINT).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
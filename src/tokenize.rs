@@ -5,6 +5,16 @@ pub enum ParseError {
     FailedToParse,
 }
 
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::QuoteMissing => write!(f, "unexpected EOF while looking for matching quote"),
+            ParseError::UnknownToken => write!(f, "syntax error: unknown token"),
+            ParseError::FailedToParse => write!(f, "syntax error"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Quote {
     SingleQuote,
@@ -23,6 +33,13 @@ impl Quote {
 pub trait Parser<'a, T>: Fn(&'a str) -> Option<(T, &'a str)> {}
 impl <'a, T, F> Parser<'a, T> for F where F: Fn(&'a str) -> Option<(T, &'a str)> {}
 
+/**
+* characters separating words: space, tab and newline
+*/
+pub fn is_separator(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n')
+}
+
 /**
 * word
 * accept escape and backslash;
@@ -32,7 +49,7 @@ pub fn raw_word(s: &str) -> Option<(&str, &str)> {
         return None;
     }
     let mut escape = false;
-    for (index, ch) in s.chars().enumerate() {
+    for (index, ch) in s.char_indices() {
         if escape {
             escape = false;
             continue;
@@ -41,7 +58,7 @@ pub fn raw_word(s: &str) -> Option<(&str, &str)> {
             escape = true;
             continue;
         }
-        if ch.is_whitespace() || ch == '\'' || ch == '"' || ch == '>' {
+        if is_separator(ch) || ch == '\'' || ch == '"' || ch == '>' || ch == '<' || ch == '|' || ch == ';' || ch == '`' || ch == '&' || s[index..].starts_with("$(") {
             if index == 0 {
                 return None;
             }
@@ -52,22 +69,35 @@ pub fn raw_word(s: &str) -> Option<(&str, &str)> {
 }
 
 
+/**
+* quoted string
+* backslash escapes inside double quotes only; single quotes take everything literally
+*/
 pub fn quoted<'a>(ch: char) -> impl Parser<'a, &'a str> {
     move |s| {
-        let mut cursor = s.chars().enumerate();
+        let mut cursor = s.char_indices();
 
         cursor.next().filter(|c| c.1 == ch)?;
 
         let mut escape = false;
+        let mut skip = 0;
         for (index, c) in cursor {
+            if index < skip {
+                continue;
+            }
             if escape {
                 escape = false;
                 continue;
             }
-            if c == '\\' {
+            if c == '\\' && ch != Quote::SingleQuote.ch() {
                 escape = true;
                 continue;
             }
+            if ch == Quote::DoubleQuote.ch() && s[index..].starts_with("$(") {
+                let (inner, _) = substitution(&s[index..])?;
+                skip = index + inner.len();
+                continue;
+            }
             if c == ch {
                 return Some((&s[..index+1], &s[index+1..]));
             }
@@ -76,6 +106,43 @@ pub fn quoted<'a>(ch: char) -> impl Parser<'a, &'a str> {
     }
 }
 
+/**
+* `$(...)` up to the balanced closing parenthesis, quotes inside included
+*/
+pub fn substitution(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with("$(") {
+        return None;
+    }
+    let mut depth = 0;
+    let mut skip = 0;
+    let mut escape = false;
+    for (index, c) in s.char_indices().skip(1) {
+        if index < skip {
+            continue;
+        }
+        if escape {
+            escape = false;
+            continue;
+        }
+        match c {
+            '\\' => escape = true,
+            '\'' | '"' => {
+                let (inner, _) = quoted(c)(&s[index..])?;
+                skip = index + inner.len();
+            },
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&s[..index + 1], &s[index + 1..]));
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
 pub fn many<'a, T>(parser: impl Parser<'a, T>) -> impl Parser<'a, Vec<T>> {
     move |mut s| {
         let mut ret = vec![];
@@ -119,7 +186,21 @@ macro_rules! join {
 }
 
 pub fn lexeme<'a, T>(parser: impl Parser<'a, T>) -> impl Parser<'a, T> {
-    move |s| parser(s.trim_start())
+    move |s| parser(skip_blank(s))
+}
+
+/**
+* skip separators and the comments among them.
+* a `#` is only a comment where a word would start, and it runs to the end of the line
+*/
+fn skip_blank(mut s: &str) -> &str {
+    loop {
+        s = s.trim_start_matches(is_separator);
+        let Some(comment) = s.strip_prefix('#') else {
+            return s;
+        };
+        s = comment.find('\n').map_or("", |end| &comment[end..]);
+    }
 }
 
 /**
@@ -127,47 +208,103 @@ pub fn lexeme<'a, T>(parser: impl Parser<'a, T>) -> impl Parser<'a, T> {
 * possibly 
 */
 pub fn trim_space(s: &str) -> Option<((), &str)> {
-    Some(((), s.trim_start()))
+    Some(((), skip_blank(s)))
 }
 
 /**
 * some character
 */
 fn word(s: &str) -> Option<(&str, &str)> {
-    let elem = choice!(quoted('\''), quoted('"'), raw_word);
+    let elem = choice!(quoted('\''), quoted('"'), quoted('`'), substitution, raw_word);
     let first = elem(s)?;
     let r = many(elem)(first.1);
     let end = first.0.len() + r.map(|(r, _)| r.iter().fold(0, |sum, x| sum + x.len())).unwrap_or(0);
     Some((&s[..end], &s[end..]))
 }
 
+/**
+* a redirection operator, with the file descriptor it applies to when written right before it
+*/
 fn redirect(s: &str) -> Option<(&str, &str)> {
-    if s.starts_with(">>") {
+    let fd = if s.starts_with(|c: char| c.is_ascii_digit()) { 1 } else { 0 };
+    let op = [">>", ">&", ">", "<"].into_iter().find(|op| s[fd..].starts_with(op))?;
+    let end = fd + op.len();
+    Some((&s[..end], &s[end..]))
+}
+
+fn connector(s: &str) -> Option<(&str, &str)> {
+    if s.starts_with("&&") || s.starts_with("||") {
         Some((&s[..2], &s[2..]))
-    } else if s.starts_with(">") {
-        Some((&s[..1], &s[1..]))
+    } else if let Some(rest) = s.strip_prefix([';', '&']) {
+        Some((&s[..1], rest))
     } else {
         None
     }
 }
 
+fn pipe(s: &str) -> Option<(&str, &str)> {
+    s.strip_prefix('|').map(|rest| (&s[..1], rest))
+}
+
+/**
+* whether `src` ends in a backslash-newline that joins it with the next line.
+* the backslash must not itself be escaped or inside single quotes
+*/
+pub fn continues_line(src: &str) -> bool {
+    let Some(line) = src.strip_suffix('\n') else {
+        return false;
+    };
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escape = false;
+    let mut word_start = true;
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let at_word_start = std::mem::replace(&mut word_start, false);
+        if escape {
+            escape = false;
+            continue;
+        }
+        match ch {
+            // a backslash in a comment does not join lines
+            '#' if at_word_start && !in_single && !in_double => return false,
+            ch if !in_single && !in_double && (is_separator(ch) || matches!(ch, ';' | '|' | '&' | '<' | '>')) => word_start = true,
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '\\' if !in_single => {
+                if chars.peek().is_none() {
+                    return true;
+                }
+                escape = true;
+            },
+            _ => {},
+        }
+    }
+    false
+}
+
 pub fn tokenize(src: &str) -> Result<Vec<&str>, ParseError> {
-    let r = join(many(choice!(lexeme(word), lexeme(redirect))), trim_space)(src);
+    let r = join(many(choice!(lexeme(redirect), lexeme(word), lexeme(connector), lexeme(pipe))), trim_space)(src);
     let Some(parsed) = r else {
         return Err(ParseError::FailedToParse);
     };
     if !parsed.1.is_empty() {
+        // only an unclosed quote stops the words on one, more input may close it
+        if parsed.1.trim_start().starts_with(['\'', '"', '`']) {
+            return Err(ParseError::QuoteMissing);
+        }
         return Err(ParseError::UnknownToken);
     };
     Ok(parsed.0.0)
 }
 
+#[allow(dead_code)]
 pub fn tokenize_old(src: &str) -> Result<Vec<&str>, ParseError> {
     let mut argv = Vec::<&str>::new();
     let mut start: Option<usize> = None;
     let mut is_in_quote: Option<Quote> = None;
     let mut escape = false;
-    for (index, ch) in src.chars().enumerate() {
+    for (index, ch) in src.char_indices() {
         // escape
         if escape {
             escape = false;
@@ -267,19 +404,91 @@ mod tests {
         let parser = quoted('\'');
         assert_eq!(parser(r#"'abc def' ghi"#), Some(("'abc def'", " ghi")));
         assert_eq!(parser(r#"'abc def ghi"#), None);
-        assert_eq!(parser(r#"'abc \' def' ghi"#), Some((r#"'abc \' def'"#, " ghi")));
+        assert_eq!(parser(r#"'abc \' def' ghi"#), Some((r#"'abc \'"#, " def' ghi")));
         assert_eq!(parser(r#"'end with escape\\'"#), Some((r#"'end with escape\\'"#, "")));
+        assert_eq!(parser(r#"'a\b' c"#), Some((r#"'a\b'"#, " c")));
+        assert_eq!(parser(r#"'can\'"#), Some((r#"'can\'"#, "")));
         assert_eq!(parser(r#"abc def"#), None);
     }
 
+    #[test]
+    fn test_quoted_double_escape() {
+        let parser = quoted('"');
+        assert_eq!(parser(r#""abc \" def" ghi"#), Some((r#""abc \" def""#, " ghi")));
+        assert_eq!(parser(r#""can\""#), None);
+    }
+
+    #[test]
+    fn test_tokenize_single_quote_backslash() {
+        let result = tokenize(r#"echo 'can\' 'a\b'"#).unwrap();
+        assert_eq!(result, ["echo", r#"'can\'"#, r#"'a\b'"#]);
+    }
+
     #[test]
     fn test_redirect() {
         let parser = redirect;
         assert_eq!(parser(">> abc"), Some((">>", " abc")));
         assert_eq!(parser("> abc"), Some((">", " abc")));
+        assert_eq!(parser(">&1"), Some((">&", "1")));
+        assert_eq!(parser("2>&1"), Some(("2>&", "1")));
+        assert_eq!(parser("1>> abc"), Some(("1>>", " abc")));
+        assert_eq!(parser("2 > abc"), None);
+        assert_eq!(parser("< abc"), Some(("<", " abc")));
         assert_eq!(parser("abc"), None);
     }
 
+    #[test]
+    fn test_pipe() {
+        let parser = pipe;
+        assert_eq!(parser("| cat"), Some(("|", " cat")));
+        assert_eq!(parser("cat"), None);
+    }
+
+    #[test]
+    fn test_tokenize_pipe() {
+        let result = tokenize("echo hi | cat").unwrap();
+        assert_eq!(result, ["echo", "hi", "|", "cat"]);
+        let result = tokenize("echo hi|cat|wc -l").unwrap();
+        assert_eq!(result, ["echo", "hi", "|", "cat", "|", "wc", "-l"]);
+        let result = tokenize("echo 'a|b' a\\|b").unwrap();
+        assert_eq!(result, ["echo", "'a|b'", "a\\|b"]);
+    }
+
+    #[test]
+    fn test_substitution() {
+        assert_eq!(substitution("$((1 + 2)) x"), Some(("$((1 + 2))", " x")));
+        assert_eq!(substitution("$(echo ')' \"(\") x"), Some(("$(echo ')' \"(\")", " x")));
+        assert_eq!(substitution("$(a $(b) c)d"), Some(("$(a $(b) c)", "d")));
+        assert_eq!(substitution("$(a"), None);
+        assert_eq!(substitution("a"), None);
+    }
+
+    #[test]
+    fn test_tokenize_substitution() {
+        let result = tokenize("echo $(( 1 + 2 ))x y").unwrap();
+        assert_eq!(result, ["echo", "$(( 1 + 2 ))x", "y"]);
+        let result = tokenize("echo a$(echo \"b c\")").unwrap();
+        assert_eq!(result, ["echo", "a$(echo \"b c\")"]);
+        let result = tokenize("echo x`echo a b`y z").unwrap();
+        assert_eq!(result, ["echo", "x`echo a b`y", "z"]);
+        let result = tokenize("echo \"a $(echo \"b\") c\"").unwrap();
+        assert_eq!(result, ["echo", "\"a $(echo \"b\") c\""]);
+    }
+
+    #[test]
+    fn test_tokenize_connector() {
+        let result = tokenize("mkdir x && cd x").unwrap();
+        assert_eq!(result, ["mkdir", "x", "&&", "cd", "x"]);
+        let result = tokenize("false||echo a|cat").unwrap();
+        assert_eq!(result, ["false", "||", "echo", "a", "|", "cat"]);
+        let result = tokenize("echo a;echo b ; ';'").unwrap();
+        assert_eq!(result, ["echo", "a", ";", "echo", "b", ";", "';'"]);
+        let result = tokenize("echo a&b '&&' \\&").unwrap();
+        assert_eq!(result, ["echo", "a", "&", "b", "'&&'", "\\&"]);
+        let result = tokenize("sleep 5& echo 2>&1").unwrap();
+        assert_eq!(result, ["sleep", "5", "&", "echo", "2>&", "1"]);
+    }
+
     #[test]
     fn test_tokenize() {
         let result = tokenize("a b c").unwrap();
@@ -305,6 +514,28 @@ mod tests {
         assert_eq!(result[1], "hello");
         assert_eq!(result[2], "world");
     }
+    #[test]
+    fn test_tab_separator() {
+        let result = tokenize("echo\ta\tb").unwrap();
+        assert_eq!(result, ["echo", "a", "b"]);
+        let result = tokenize("\t echo \t a\t").unwrap();
+        assert_eq!(result, ["echo", "a"]);
+    }
+
+    #[test]
+    fn test_newline_separator() {
+        let result = tokenize("echo a\nb\n").unwrap();
+        assert_eq!(result, ["echo", "a", "b"]);
+        let result = tokenize("echo 'a\nb'\n").unwrap();
+        assert_eq!(result, ["echo", "'a\nb'"]);
+    }
+
+    #[test]
+    fn test_other_whitespace_is_not_separator() {
+        let result = tokenize("echo a\u{a0}b").unwrap();
+        assert_eq!(result, ["echo", "a\u{a0}b"]);
+    }
+
     #[test]
     fn test_single_quote() {
         let result = tokenize("echo 'abcdef ghijkl'").unwrap();
@@ -321,10 +552,36 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn test_continues_line() {
+        assert!(continues_line("echo a\\\n"));
+        assert!(!continues_line("echo a\\\\\n"));
+        assert!(continues_line("echo a\\\\\\\n"));
+        assert!(!continues_line("echo 'a\\\n"));
+        assert!(continues_line("echo 'a' \"b\\\n"));
+        assert!(!continues_line("echo a\n"));
+        assert!(!continues_line("echo a\\"));
+        assert!(!continues_line("echo a # b\\\n"));
+        assert!(continues_line("echo a#b\\\n"));
+        assert!(continues_line("echo \"a #\" b\\\n"));
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(tokenize("echo hi # ignored").unwrap(), ["echo", "hi"]);
+        assert_eq!(tokenize("# only a comment\n").unwrap(), Vec::<&str>::new());
+        assert_eq!(tokenize("echo a#b '#' \"#\" \\#").unwrap(), ["echo", "a#b", "'#'", "\"#\"", "\\#"]);
+        assert_eq!(tokenize("echo a;#b c").unwrap(), ["echo", "a", ";"]);
+        assert_eq!(tokenize("echo a # it's").unwrap(), ["echo", "a"]);
+    }
+
+    #[test]
     fn test_missing_quote() {
         let result = tokenize("echo 'a\"b").expect_err("expect missing quote error");
         assert_eq!(result, ParseError::QuoteMissing);
+        assert_eq!(tokenize("echo a\"b c\n"), Err(ParseError::QuoteMissing));
+        assert_eq!(tokenize("echo `date"), Err(ParseError::QuoteMissing));
+        assert_eq!(tokenize("echo 'a\nb'").unwrap(), ["echo", "'a\nb'"]);
+        assert_eq!(tokenize("echo $(date"), Err(ParseError::UnknownToken));
     }
 
     #[test]
@@ -340,8 +597,14 @@ mod tests {
         let result = tokenize("echo a > b").unwrap();
         assert_eq!(result, ["echo", "a", ">", "b"]);
         let result = tokenize("echo a 1> b").unwrap();
-        assert_eq!(result, ["echo", "a", "1", ">", "b"]);
+        assert_eq!(result, ["echo", "a", "1>", "b"]);
         let result = tokenize("echo a 2> b").unwrap();
+        assert_eq!(result, ["echo", "a", "2>", "b"]);
+        let result = tokenize("echo a 2 > b").unwrap();
         assert_eq!(result, ["echo", "a", "2", ">", "b"]);
+        let result = tokenize("cmd > out 2>&1").unwrap();
+        assert_eq!(result, ["cmd", ">", "out", "2>&", "1"]);
+        let result = tokenize("cat <input.txt").unwrap();
+        assert_eq!(result, ["cat", "<", "input.txt"]);
     }
 }
@@ -8,13 +8,17 @@ struct UnescapeState {
 fn unescape_inside(ch: char, peek: Option<&char>, mut state: UnescapeState)-> (Option<char>, UnescapeState) {
     if state.escape {
         state.escape = false;
+        // backslash-newline is a line continuation and disappears
+        if ch == '\n' {
+            return (None, state);
+        }
         return (Some(ch), state);
     }
     if ch == '\\' {
         let to_escape = match state.is_in_quote {
             None => true,
-            Some(Quote::SingleQuote) => peek.filter(|c| **c == Quote::SingleQuote.ch()).is_some(),
-            Some(Quote::DoubleQuote) => peek.filter(|c| **c == Quote::DoubleQuote.ch() || **c == '\\').is_some(),
+            Some(Quote::SingleQuote) => false,
+            Some(Quote::DoubleQuote) => peek.filter(|c| matches!(**c, '"' | '\\' | '$' | '`' | '\n')).is_some(),
         };
         if to_escape {
             state.escape = true;
@@ -53,6 +57,10 @@ fn unescape_inside(ch: char, peek: Option<&char>, mut state: UnescapeState)-> (O
     (Some(ch), state)
 }
 
+/**
+* remove the quotes and escapes of a raw token.
+* NUL bytes are dropped as bash does, an argument cannot hold them
+*/
 pub fn unescape(src: &str) -> String {
     let mut result = String::new();
     let mut state = UnescapeState {
@@ -66,7 +74,7 @@ pub fn unescape(src: &str) -> String {
         let unescaped = unescape_inside(ch, chars.peek(), state);
         state = unescaped.1;
         // println!("{} {} {:?} {:?}", ch, state.escape, state.is_in_quote, unescaped.0);
-        if let Some(r) = unescaped.0 {
+        if let Some(r) = unescaped.0.filter(|r| *r != '\0') {
             result.push(r);
         }
     }
@@ -78,6 +86,12 @@ pub fn unescape(src: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unescape_drops_nul() {
+        assert_eq!(unescape("a\0b"), "ab");
+        assert_eq!(unescape("'a\0' \"\0b\" \\\0"), "a b ");
+    }
+
     #[test]
     fn test_unescape_none() {
         assert_eq!(unescape("abcdef"), "abcdef");
@@ -137,4 +151,31 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_double_quote_escapable () {
+        assert_eq!(unescape(r#""\$HOME""#), r#"$HOME"#);
+        assert_eq!(unescape(r#""\`cmd\`""#), r#"`cmd`"#);
+        assert_eq!(unescape(r#""\"""#), r#"""#);
+        assert_eq!(unescape(r#""\\""#), r#"\"#);
+        assert_eq!(unescape("\"a\\\nb\""), "ab");
+    }
+
+    #[test]
+    fn test_double_quote_other_backslash_is_literal () {
+        assert_eq!(unescape(r#""\n\t\a""#), r#"\n\t\a"#);
+    }
+
+    #[test]
+    fn test_unquoted_backslash_newline () {
+        assert_eq!(unescape("a\\\nb"), "ab");
+    }
+
+    #[test]
+    fn test_single_quote_is_literal () {
+        assert_eq!(unescape(r#"'can\'"#), r#"can\"#);
+        assert_eq!(unescape(r#"'a\b'"#), r#"a\b"#);
+        assert_eq!(unescape(r#"'a\\'"#), r#"a\\"#);
+        assert_eq!(unescape(r#"'a\'\''b'"#), r#"a\'b"#);
+    }
+
 }
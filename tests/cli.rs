@@ -0,0 +1,361 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run_with_stdin(script: &str) -> Output {
+    run_shell(&[], script)
+}
+
+fn run_shell(args: &[&str], script: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("should spawn the shell");
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_piped_script_has_no_prompt() {
+    let output = run_with_stdin("echo hi\necho there\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\nthere\n");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_eof_exits_with_last_status() {
+    let output = run_with_stdin("nosuchcommand_for_test\n");
+    assert_eq!(output.status.code(), Some(127));
+}
+
+#[test]
+fn test_debug_traces_stages() {
+    let output = run_shell(&["--debug"], "echo 'a b' > /dev/null\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        concat!(
+            "+ tokens: [\"echo\", \"'a b'\", \">\", \"/dev/null\"]\n",
+            "+ argv: [\"echo\", \"a b\"]\n",
+        )
+    );
+}
+
+#[test]
+fn test_invalid_utf8_input_is_read() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("should spawn the shell");
+    child.stdin.take().unwrap().write_all(b"echo a\xffb\necho after\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\u{fffd}b\nafter\n");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_pipeline() {
+    let output = run_with_stdin("echo hi | cat\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+
+    let output = run_with_stdin("printf 'b\\na\\nb\\n' | sort | uniq\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb\n");
+
+    let output = run_with_stdin("type echo | cat\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "echo is a shell builtin\n");
+}
+
+#[test]
+fn test_last_status_expansion() {
+    let output = run_with_stdin("false\necho $?\ntrue\necho \"status $?\" '$?'\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\nstatus 0 $?\n");
+}
+
+#[test]
+fn test_exit_without_argument_uses_last_status() {
+    let output = run_with_stdin("sh -c 'exit 3'\nexit\necho unreachable\n");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
+
+#[test]
+fn test_variable_expansion() {
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .env("HOME", "/home/tester")
+        .env("PATH", "/usr/bin:/bin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(b"echo $HOME\necho ${PATH}\necho '$HOME'\necho \"$NO_SUCH_VAR\"x\n")?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "/home/tester\n/usr/bin:/bin\n$HOME\nx\n"
+    );
+}
+
+#[test]
+fn test_command_separator() {
+    let output = run_with_stdin("echo a ; echo b\necho c ;; echo d ;\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb\nc\nd\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_arithmetic_expansion() {
+    let output = run_with_stdin("echo $(( -5 + 3 )) $((0xff)) $((010))\necho $(( 1.5 ))\necho $?\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "-2 255 8\n1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("syntax error"));
+}
+
+#[test]
+fn test_command_substitution() {
+    let output = run_with_stdin("echo $(echo hi)\necho \"$(echo a | tr a b)\" `echo c`\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\nb c\n");
+}
+
+#[test]
+fn test_noexec_reports_syntax_errors_only() {
+    let output = run_shell(&["-n"], "echo hi\necho a | | cat\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "syntax error near unexpected token `|'\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_history() {
+    let output = run_with_stdin("echo a\n\necho b > /dev/null\nhistory | cat\nhistory 1\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a\n    1  echo a\n    2  echo b > /dev/null\n    3  history | cat\n    4  history 1\n"
+    );
+}
+
+#[test]
+fn test_unclosed_quote_continues() {
+    let output = run_with_stdin("echo 'a\nb' \"c\nd\"\necho done\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb c\nd\ndone\n");
+}
+
+#[test]
+fn test_trailing_backslash_continues() {
+    let output = run_with_stdin("echo a\\\nb\necho 'c\\\nd'\necho e\\\\\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ab\nc\\\nd\ne\\\n");
+}
+
+#[test]
+fn test_comments() {
+    let output = run_with_stdin("# a comment\necho hi # ignored\necho a#b '#' \\#\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\na#b # #\n");
+}
+
+#[test]
+fn test_interrupt_does_not_kill_the_shell() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("should spawn the shell");
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let killed = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    child.stdin.take().unwrap().write_all(b"sh -c 'kill -INT $$; echo unreached'\necho alive\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "alive\n");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_background_in_script() {
+    let output = run_with_stdin("echo bg &\nsleep 0.2\necho after\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bg\nafter\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let output = run_with_stdin("set -m\nsleep 0.1 &\nsleep 0.3\necho after\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "after\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("[1] "), "{}", stderr);
+    assert!(stderr.ends_with("[1]  Done\tsleep 0.1\n"), "{}", stderr);
+}
+
+#[test]
+fn test_source_keeps_variables() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_source_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("vars.sh");
+    std::fs::write(&script, "greeting=hello\ncd /\n").unwrap();
+    let output = run_with_stdin(&format!(
+        "source {}\necho $greeting $(pwd)\n. {}/missing.sh\necho $?\n",
+        script.display(),
+        dir.display()
+    ));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello /\n1\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("shell: source: {}/missing.sh: No such file or directory\n", dir.display())
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_builtin_errors_are_prefixed() {
+    let output = run_with_stdin(concat!(
+        "cd /nonexistent_for_test\n",
+        "cd /etc/passwd\n",
+        "type nosuchcommand_for_test\n",
+        "echo $?\n",
+        "set -z\n",
+        "exit abc\n",
+        "echo $?\n",
+    ));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        concat!(
+            "shell: cd: /nonexistent_for_test: No such file or directory\n",
+            "shell: cd: /etc/passwd: Not a directory\n",
+            "shell: type: nosuchcommand_for_test: not found\n",
+            "shell: set: -z: invalid option\n",
+            "shell: exit: abc: numeric argument required\n",
+        )
+    );
+}
+
+#[test]
+fn test_builtin_errors_follow_stderr_redirect() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_builtin_stderr_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = run_with_stdin(&format!("cd {}\ncd missing 2>file\nnosuchcommand_for_test 2>>file\n", dir.display()));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(
+        std::fs::read_to_string(dir.join("file")).unwrap(),
+        "shell: cd: missing: No such file or directory\nnosuchcommand_for_test: command not found\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_script_argument() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_script_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("run.sh");
+    std::fs::write(&script, "echo $0 $1 \"$2\" ${3}x\nsh -c 'exit 4'\n").unwrap();
+    let path = script.display().to_string();
+    let output = run_shell(&[&path, "one", "two words"], "echo from stdin\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{} one two words x\n", path));
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = run_shell(&[&format!("{}/missing.sh", dir.display())], "");
+    assert_eq!(output.status.code(), Some(127));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("shell: {}/missing.sh: No such file or directory\n", dir.display())
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stray_reserved_word() {
+    let output = run_with_stdin("done\necho $?\necho fi ; }\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "syntax error near unexpected token `done'\nsyntax error near unexpected token `}'\n"
+    );
+}
+
+#[test]
+fn test_read_builtin() {
+    let output = run_with_stdin("read x\n  hello   world  \necho \"[$x]\"\nread a b < /dev/null\necho $?\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[hello   world]\n1\n");
+
+    // the pipe is read, not the next line of the script
+    let output = run_with_stdin("echo piped | read x\necho $?\nprintf '' | read x\necho $?\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n1\n");
+}
+
+#[test]
+fn test_expanded_operators_stay_words() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_operators_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = run_with_stdin(&format!(
+        "cd {}\nx='>'\necho a $x out\ny='|'\necho a $y cat\necho $(echo '>') zz\nz=v=1\n$z\n",
+        dir.display()
+    ));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a > out\na | cat\n> zz\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "v=1: command not found\n");
+    assert!(!dir.join("out").exists() && !dir.join("zz").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unset_in_substitution_stays_there() {
+    let output = run_with_stdin("x=$(unset PATH)\nls / > /dev/null\necho $?\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+}
+
+#[test]
+fn test_settings_from_shell_variables() {
+    let output = run_with_stdin("SHELL_CMD_TIMEOUT=1\nsleep 3\necho $?\nSHELL_DEBUG=1\necho hi > /dev/null\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "124\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("+ argv: [\"echo\", \"hi\"]\n"));
+}
+
+#[test]
+fn test_audit_log_covers_scripts() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_audit_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("audit.log");
+    let script = dir.join("run.sh");
+    std::fs::write(&script, "echo from script > /dev/null\n# only a comment\nfalse\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .arg(&script)
+        .env("SHELL_AUDIT_LOG", &log)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let entries: Vec<String> = std::fs::read_to_string(&log).unwrap()
+        .lines()
+        .map(|line| line.split_once('\t').unwrap().1.to_string())
+        .collect();
+    assert_eq!(entries, ["0\techo from script > /dev/null", "1\tfalse"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_errors_name_script_and_line() {
+    let dir = std::env::temp_dir().join(format!("shell_cli_frames_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.sh"), "echo lib\n\necho a \\\n  | | cat\necho 'open\n").unwrap();
+    std::fs::write(dir.join("run.sh"), "source lib.sh\necho after\n| x\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .arg("run.sh")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "lib\nafter\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        concat!(
+            "shell: lib.sh:3: syntax error near unexpected token `|'\n",
+            "shell: lib.sh:5: unexpected EOF while looking for matching quote\n",
+            "shell: run.sh:3: syntax error near unexpected token `|'\n",
+        )
+    );
+
+    let output = run_with_stdin(&format!("source {}/lib.sh\n| x\n", dir.display()));
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("\nsyntax error near unexpected token `|'\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_shell_version_is_not_exported() {
+    let output = run_with_stdin("echo $SHELL_VERSION\nsh -c 'echo \"[$SHELL_VERSION]\"'\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}\n[]\n", env!("CARGO_PKG_VERSION")));
+}